@@ -68,7 +68,7 @@ async fn root(State(state): State<Arc<Mutex<AppState>>>) -> Result<Json<Value>,
     let gb = GrowthBook {
         context: Context {
             attributes: user_attributes.clone(),
-            features: features.clone(),
+            features,
             ..Default::default()
         },
         tracking_callback: Some(tracking_callback),
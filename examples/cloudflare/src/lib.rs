@@ -1,3 +1,6 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
 use chrono::Utc;
 use growthbook_sdk_rust::growthbook::GrowthBook;
 use growthbook_sdk_rust::model::Context;
@@ -5,11 +8,41 @@ use growthbook_sdk_rust::model::Experiment;
 use growthbook_sdk_rust::model::ExperimentResult;
 use growthbook_sdk_rust::model::TrackingCallback;
 use growthbook_sdk_rust::repository::FeatureRepository;
+use growthbook_sdk_rust::transport::{FeatureRequest, FeatureResponse, FeatureTransport, TransportError};
 use serde_json::json;
 use worker::*;
 
 mod utils;
 
+// fetches features through the Workers runtime instead of reqwest
+#[derive(Debug)]
+struct WorkerFetchTransport;
+
+#[async_trait(?Send)]
+impl FeatureTransport for WorkerFetchTransport {
+    async fn send(&self, request: FeatureRequest) -> std::result::Result<FeatureResponse, TransportError> {
+        let to_transport_error = |e: Error| TransportError { message: e.to_string() };
+
+        let headers = Headers::new();
+        for (name, value) in request.headers.iter() {
+            headers.set(name, value).map_err(to_transport_error)?;
+        }
+        let mut init = RequestInit::new();
+        init.with_method(Method::from(request.method.clone())).with_headers(headers);
+        if let Some(body) = request.body {
+            init.with_body(Some(body.into()));
+        }
+
+        let worker_request = Request::new_with_init(&request.url, &init).map_err(to_transport_error)?;
+        let mut response = Fetch::Request(worker_request).send().await.map_err(to_transport_error)?;
+        Ok(FeatureResponse {
+            status: response.status_code(),
+            headers: response.headers().entries().collect(),
+            body: response.text().await.map_err(to_transport_error)?,
+        })
+    }
+}
+
 fn log_request(req: &Request) {
     console_log!(
         "{} - [{}], located at: {:?}, within: {}",
@@ -33,6 +66,7 @@ pub async fn main(req: Request, env: Env, _ctx: worker::Context) -> Result<Respo
         .get_async("/features", |_, _ctx| async move {
             let mut repo = FeatureRepository {
                 client_key: Some("java_NsrWldWd5bxQJZftGsWKl7R2yD2LtAK8C8EUYh9L8".to_string()),
+                transport: Some(Arc::new(WorkerFetchTransport)),
                 ..Default::default()
             };
             let features = repo.get_features().await;
@@ -55,7 +89,7 @@ pub async fn main(req: Request, env: Env, _ctx: worker::Context) -> Result<Respo
             let gb = GrowthBook {
                 context: Context {
                     attributes: user_attributes,
-                    features: features.clone(),
+                    features,
                     ..Default::default()
                 },
                 tracking_callback: Some(tracking_callback),
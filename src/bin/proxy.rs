@@ -0,0 +1,70 @@
+use std::env;
+use std::net::SocketAddr;
+use std::num::NonZeroU64;
+use std::process;
+use std::time::Duration;
+
+use growthbook_sdk_rust::proxy::FeatureProxy;
+use growthbook_sdk_rust::registry::RepositoryRegistry;
+use growthbook_sdk_rust::repository::FeatureRepository;
+
+// configured through the environment:
+//   GROWTHBOOK_CLIENT_KEYS     comma separated client keys, `key:decryptionKey` for encrypted payloads (required)
+//   GROWTHBOOK_API_HOST        upstream to poll, defaults to https://cdn.growthbook.io
+//   GROWTHBOOK_POLL_INTERVAL   seconds between upstream refreshes, at least 1, defaults to 60
+//   GROWTHBOOK_PROXY_ADDRESS   address to listen on, defaults to 0.0.0.0:3100
+#[tokio::main]
+async fn main() {
+    let client_keys: Vec<(String, Option<String>)> = env::var("GROWTHBOOK_CLIENT_KEYS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .map(|key| match key.split_once(':') {
+            Some((client_key, decryption_key)) => (client_key.to_string(), Some(decryption_key.to_string())),
+            None => (key.to_string(), None),
+        })
+        .collect();
+    if client_keys.is_empty() {
+        eprintln!("GROWTHBOOK_CLIENT_KEYS is not set");
+        process::exit(1);
+    }
+    let api_host = env::var("GROWTHBOOK_API_HOST").unwrap_or_else(|_| "https://cdn.growthbook.io".to_string());
+    // zero would make the polling interval panic
+    let poll_interval = match env::var("GROWTHBOOK_POLL_INTERVAL").map(|value| value.parse::<NonZeroU64>()) {
+        Ok(Ok(seconds)) => Duration::from_secs(seconds.get()),
+        Ok(Err(e)) => {
+            eprintln!("Invalid GROWTHBOOK_POLL_INTERVAL: {}", e);
+            process::exit(1);
+        }
+        Err(_) => Duration::from_secs(60),
+    };
+    let address: SocketAddr = match env::var("GROWTHBOOK_PROXY_ADDRESS")
+        .unwrap_or_else(|_| "0.0.0.0:3100".to_string())
+        .parse()
+    {
+        Ok(address) => address,
+        Err(e) => {
+            eprintln!("Invalid GROWTHBOOK_PROXY_ADDRESS: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let registry = RepositoryRegistry::new(FeatureRepository {
+        ttl_seconds: poll_interval.as_secs() as i64,
+        ..Default::default()
+    });
+    let proxy = FeatureProxy::new(registry, &api_host, &client_keys);
+    let _polling = proxy.start_polling(poll_interval);
+
+    println!("Proxying {} client key(s) from {} on {}", client_keys.len(), api_host, address);
+    let server = axum::Server::bind(&address)
+        .serve(proxy.router().into_make_service())
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        });
+    if let Err(e) = server.await {
+        eprintln!("Proxy server failed: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,331 @@
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::Thread;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use log::error;
+
+use crate::model::FeatureMap;
+use crate::repository::{FeatureRefreshCallback, FeatureRepository, FeatureRepositoryError, FeatureRepositoryErrorCallback, RefreshOutcome};
+use crate::transport::{FeatureRequest, FeatureResponse, FeatureTransport, TransportError, TransportOptions};
+
+// same caching, decryption and refresh callbacks as FeatureRepository, for applications without an async runtime.
+// refreshes happen on the calling thread once the ttl has expired, retries sleep that thread
+#[derive(Debug, Clone)]
+pub struct BlockingFeatureRepository {
+    pub repository: FeatureRepository,
+}
+
+impl Default for BlockingFeatureRepository {
+    fn default() -> Self {
+        BlockingFeatureRepository::new(FeatureRepository::default())
+    }
+}
+
+impl BlockingFeatureRepository {
+    // keeps the transport of `repository`. an async reqwest one (the default) needs a tokio runtime, so it is
+    // swapped for a BlockingReqwestTransport built with the same proxy and tls options
+    pub fn new(repository: FeatureRepository) -> Self {
+        let transport = match repository.transport {
+            Some(transport) if transport.reqwest_client().is_none() => Some(transport),
+            Some(transport) => {
+                let options = transport.reqwest_options().unwrap_or_default();
+                match BlockingReqwestTransport::with_options(&options) {
+                    Ok(blocking) => Some(Arc::new(blocking) as Arc<dyn FeatureTransport>),
+                    // fetching without the caller's proxy or tls settings is worse than not fetching
+                    Err(e) => {
+                        error!("Error building blocking transport from the transport options: {}", e);
+                        None
+                    }
+                }
+            }
+            None => Some(Arc::new(BlockingReqwestTransport::default()) as Arc<dyn FeatureTransport>),
+        };
+        BlockingFeatureRepository {
+            repository: FeatureRepository { transport, ..repository },
+        }
+    }
+
+    pub fn add_refresh_callback(&mut self, callback: FeatureRefreshCallback) {
+        self.repository.add_refresh_callback(callback);
+    }
+
+    pub fn clear_refresh_callbacks(&mut self) {
+        self.repository.clear_refresh_callbacks();
+    }
+
+    pub fn get_features(&mut self) -> Arc<FeatureMap> {
+        // a failed load backs off, so an outage doesn't block every read on the retry policy
+        if self.repository.is_refresh_due() {
+            let _ = self.load();
+        }
+        self.repository.current_features()
+    }
+
+    pub fn add_error_callback(&mut self, callback: FeatureRepositoryErrorCallback) {
+        self.repository.add_error_callback(callback);
+    }
+
+    // fetches right away regardless of ttl_seconds
+    pub fn refresh(&mut self) -> Result<RefreshOutcome, FeatureRepositoryError> {
+        let repository = &mut self.repository;
+        let timeout = repository.timeout;
+        block_on(repository.load_features_with(timeout, sleep))
+    }
+
+    fn load(&mut self) -> Result<RefreshOutcome, FeatureRepositoryError> {
+        let repository = &mut self.repository;
+        block_on(async {
+            if repository.load_cached_features().await {
+                return Ok(RefreshOutcome::Unchanged);
+            }
+            let timeout = repository.timeout;
+            repository.load_features_with(timeout, sleep).await
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BlockingReqwestTransport {
+    pub client: reqwest::blocking::Client,
+}
+
+impl BlockingReqwestTransport {
+    pub fn with_options(options: &TransportOptions) -> Result<Self, TransportError> {
+        let settings = options.reqwest_settings()?;
+        let mut builder = reqwest::blocking::Client::builder();
+        if let Some(proxy) = settings.proxy {
+            builder = builder.proxy(proxy);
+        }
+        #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+        {
+            for certificate in settings.root_certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+            if let Some(identity) = settings.identity {
+                builder = builder.identity(identity);
+            }
+        }
+        let client = builder.build().map_err(|e| TransportError { message: e.to_string() })?;
+        Ok(BlockingReqwestTransport { client })
+    }
+}
+
+// does all of its work on the first poll, so it is only meant to be driven by BlockingFeatureRepository
+#[async_trait]
+impl FeatureTransport for BlockingReqwestTransport {
+    async fn send(&self, request: FeatureRequest) -> Result<FeatureResponse, TransportError> {
+        let method = reqwest::Method::from_bytes(request.method.as_bytes()).map_err(|e| TransportError { message: e.to_string() })?;
+        let mut builder = self.client.request(method, &request.url).timeout(request.timeout);
+        for (name, value) in request.headers.iter() {
+            builder = builder.header(name, value);
+        }
+        if let Some(body) = request.body {
+            builder = builder.body(body);
+        }
+
+        let res = builder.send().map_err(|e| TransportError { message: e.to_string() })?;
+        let status = res.status().as_u16();
+        let headers = res
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| value.to_str().ok().map(|value| (name.to_string(), value.to_string())))
+            .collect();
+        let body = res.text().map_err(|e| TransportError { message: e.to_string() })?;
+        Ok(FeatureResponse { status, headers, body })
+    }
+}
+
+async fn sleep(delay: Duration) -> bool {
+    std::thread::sleep(delay);
+    true
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+// minimal executor, parks the current thread until the future is woken again
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut context = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut context) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => std::thread::park_timeout(Duration::from_millis(100)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::{Arc, RwLock};
+    use std::time::Duration;
+
+    use crate::blocking::{BlockingFeatureRepository, BlockingReqwestTransport};
+    use crate::repository::{FeatureRefreshCallback, FeatureRepository, FeatureRepositoryError, RetryPolicy};
+    use crate::transport::{FeatureTransport, ReqwestTransport, TransportOptions};
+
+    const FEATURES: &str = r#"{"features": {"greeting": {"defaultValue": "hello"}, "dark_mode": {"defaultValue": false}}}"#;
+
+    #[test]
+    fn test_blocking_get_features() {
+        let mut server = mockito::Server::new();
+        let mock = server
+            .mock("GET", "/api/features/key_for_blocking")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(FEATURES)
+            .expect(1)
+            .create();
+
+        let count = Arc::new(AtomicU32::new(0));
+        let callback_count = count.clone();
+        let mut repository = BlockingFeatureRepository::new(FeatureRepository {
+            api_host: server.url(),
+            client_key: Some("key_for_blocking".to_string()),
+            ..Default::default()
+        });
+        repository.add_refresh_callback(FeatureRefreshCallback(Box::new(move |features| {
+            assert_eq!(features.len(), 2);
+            callback_count.fetch_add(1, Ordering::SeqCst);
+        })));
+
+        assert_eq!(repository.get_features().len(), 2);
+        // served from memory until the ttl expires
+        assert_eq!(repository.get_features().len(), 2);
+        mock.assert();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_blocking_encrypted_features() {
+        let mut server = mockito::Server::new();
+        server
+            .mock("GET", "/api/features/key_for_blocking_encrypted")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"features": {}, "encryptedFeatures": "UqANSnJ7xTTK9y2PALtnwQ==.BZAstXrI9eh9qlvp7VinD8CKk9ZE8755vnFtkClJNYstTUwF4FKwWWq84F/DFTe+2Xlzbys83S1Ih6XIFhoigKIQeImlnzR3GJ6Bvj3REbKccw9TJz4bX3ozFzSNBbZbLAynnd9aTLK0PAYASLXKtIaAs/K0WSbV7mM95CVMt9DU7w1TKme/tQcqfEn+CJhi2WHNdEzGs18j9t7zXcRgdAvXizLzP7HdOnCmfXy9bZbpqWmAdUBZ0yhmb2PGXa5FBwet7h1MV0kRFX++WocwjA=="}"#)
+            .create();
+
+        let mut repository = BlockingFeatureRepository::new(FeatureRepository {
+            api_host: server.url(),
+            client_key: Some("key_for_blocking_encrypted".to_string()),
+            decryption_key: Arc::new(RwLock::new(Some("BhB1wORFmZLTDjbvstvS8w==".to_string()))),
+            ..Default::default()
+        });
+        assert_eq!(repository.get_features().len(), 1);
+    }
+
+    #[test]
+    fn test_blocking_retries_failed_fetch() {
+        let mut server = mockito::Server::new();
+        let failure = server
+            .mock("GET", "/api/features/key_for_blocking_retry")
+            .with_status(503)
+            .expect(1)
+            .create();
+        server
+            .mock("GET", "/api/features/key_for_blocking_retry")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(FEATURES)
+            .create();
+
+        let mut repository = BlockingFeatureRepository::new(FeatureRepository {
+            api_host: server.url(),
+            client_key: Some("key_for_blocking_retry".to_string()),
+            retry_policy: RetryPolicy {
+                base_delay: Duration::from_millis(10),
+                jitter: 0.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        assert_eq!(repository.get_features().len(), 2);
+        failure.assert();
+    }
+
+    #[test]
+    fn test_blocking_refresh_reports_errors() {
+        let mut server = mockito::Server::new();
+        server.mock("GET", "/api/features/key_for_blocking_errors").with_status(404).create();
+
+        let mut repository = BlockingFeatureRepository::new(FeatureRepository {
+            api_host: server.url(),
+            client_key: Some("key_for_blocking_errors".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(repository.refresh(), Err(FeatureRepositoryError::Status(404)));
+    }
+
+    #[test]
+    fn test_blocking_reads_back_off_after_a_failed_load() {
+        let mut server = mockito::Server::new();
+        let failure = server
+            .mock("GET", "/api/features/key_for_blocking_backoff")
+            .with_status(404)
+            .expect(1)
+            .create();
+
+        let mut repository = BlockingFeatureRepository::new(FeatureRepository {
+            api_host: server.url(),
+            client_key: Some("key_for_blocking_backoff".to_string()),
+            ..Default::default()
+        });
+        assert!(repository.get_features().is_empty());
+        assert!(repository.get_features().is_empty());
+        failure.assert();
+    }
+
+    #[test]
+    fn test_blocking_keeps_configured_transport() {
+        let options = TransportOptions {
+            proxy: Some("http://proxy.internal:3128".to_string()),
+            ..Default::default()
+        };
+        let transport: Arc<dyn FeatureTransport> = Arc::new(BlockingReqwestTransport::with_options(&options).unwrap());
+        let repository = BlockingFeatureRepository::new(FeatureRepository {
+            transport: Some(transport.clone()),
+            ..Default::default()
+        });
+        assert!(Arc::ptr_eq(repository.repository.transport.as_ref().unwrap(), &transport));
+
+        // the default async transport can't run without a runtime
+        let repository = BlockingFeatureRepository::default();
+        assert!(repository.repository.transport.unwrap().reqwest_client().is_none());
+    }
+
+    #[test]
+    fn test_blocking_builds_its_client_with_the_async_transport_options() {
+        let mut proxy = mockito::Server::new();
+        let proxied = proxy
+            .mock("GET", "/api/features/key_for_blocking_proxy")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(FEATURES)
+            .create();
+
+        let options = TransportOptions {
+            proxy: Some(proxy.url()),
+            ..Default::default()
+        };
+        let mut repository = BlockingFeatureRepository::new(FeatureRepository {
+            api_host: "http://features.invalid".to_string(),
+            client_key: Some("key_for_blocking_proxy".to_string()),
+            transport: Some(Arc::new(ReqwestTransport::with_options(&options).unwrap())),
+            ..Default::default()
+        });
+        assert_eq!(repository.get_features().len(), 2);
+        proxied.assert();
+    }
+}
@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Debug, Display};
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedPayload {
+    pub saved_at: i64,
+    // exactly as the server sent it, so encrypted features stay encrypted in the cache
+    pub payload: Value,
+}
+
+impl CachedPayload {
+    pub fn now(payload: Value) -> Self {
+        CachedPayload {
+            saved_at: chrono::Utc::now().timestamp(),
+            payload,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheError {
+    pub message: String,
+}
+
+impl Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for CacheError {}
+
+impl From<io::Error> for CacheError {
+    fn from(e: io::Error) -> Self {
+        CacheError { message: e.to_string() }
+    }
+}
+
+// FeatureRepository reads this before going to the network and writes every good payload back,
+// implement it to share payloads between processes, the key is the client key
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+pub trait FeatureCache: Debug + Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<CachedPayload>, CacheError>;
+    async fn set(&self, key: &str, payload: CachedPayload) -> Result<(), CacheError>;
+}
+
+// clones share the same entries, so one instance can back several repositories in a process
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryFeatureCache {
+    entries: Arc<RwLock<HashMap<String, CachedPayload>>>,
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl FeatureCache for InMemoryFeatureCache {
+    async fn get(&self, key: &str) -> Result<Option<CachedPayload>, CacheError> {
+        match self.entries.read() {
+            Ok(entries) => Ok(entries.get(key).cloned()),
+            Err(_) => Err(CacheError {
+                message: "cache lock poisoned".to_string(),
+            }),
+        }
+    }
+
+    async fn set(&self, key: &str, payload: CachedPayload) -> Result<(), CacheError> {
+        match self.entries.write() {
+            Ok(mut entries) => {
+                entries.insert(key.to_string(), payload);
+                Ok(())
+            }
+            Err(_) => Err(CacheError {
+                message: "cache lock poisoned".to_string(),
+            }),
+        }
+    }
+}
+
+// prefixes every key, so repositories of different api hosts can share one cache despite equal client keys
+#[derive(Debug, Clone)]
+pub struct NamespacedFeatureCache {
+    pub namespace: String,
+    pub cache: Arc<dyn FeatureCache>,
+}
+
+impl NamespacedFeatureCache {
+    pub fn new(namespace: &str, cache: Arc<dyn FeatureCache>) -> Self {
+        NamespacedFeatureCache {
+            namespace: namespace.to_string(),
+            cache,
+        }
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}|{}", self.namespace, key)
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl FeatureCache for NamespacedFeatureCache {
+    async fn get(&self, key: &str) -> Result<Option<CachedPayload>, CacheError> {
+        self.cache.get(&self.key(key)).await
+    }
+
+    async fn set(&self, key: &str, payload: CachedPayload) -> Result<(), CacheError> {
+        self.cache.set(&self.key(key), payload).await
+    }
+}
+
+// one json file per client key in `directory`, survives restarts and can be shared by processes on a host
+#[derive(Debug, Clone)]
+pub struct FileFeatureCache {
+    pub directory: PathBuf,
+}
+
+impl FileFeatureCache {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        FileFeatureCache { directory: directory.into() }
+    }
+
+    pub fn path_for(&self, key: &str) -> PathBuf {
+        self.directory.join(format!("growthbook-{}.json", encode_file_name(key)))
+    }
+}
+
+// payloads are small, so plain blocking file io is fine here
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl FeatureCache for FileFeatureCache {
+    async fn get(&self, key: &str) -> Result<Option<CachedPayload>, CacheError> {
+        let content = match fs::read_to_string(self.path_for(key)) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        serde_json::from_str(&content).map(Some).map_err(|e| CacheError {
+            message: format!("invalid cache file: {}", e),
+        })
+    }
+
+    async fn set(&self, key: &str, payload: CachedPayload) -> Result<(), CacheError> {
+        let content = serde_json::to_vec(&payload).map_err(|e| CacheError { message: e.to_string() })?;
+        Ok(write_atomically(&self.path_for(key), &content)?)
+    }
+}
+
+// percent-encodes everything but lowercase ascii letters, digits, `-` and `_`, so distinct keys never
+// share a file, also on case-insensitive file systems
+pub(crate) fn encode_file_name(key: &str) -> String {
+    key.bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+// the payload goes to a temporary file next to the target and is renamed over it,
+// so readers see either the old or the new file and never a partial one
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// every write gets its own temp file, concurrent writers to the same path must not truncate each other's
+pub(crate) fn write_atomically(path: &Path, content: &[u8]) -> io::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "cache path has no file name"))?;
+    let mut temp_name = file_name.to_os_string();
+    temp_name.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = fs::File::create(&temp_path)
+        .and_then(|mut file| file.write_all(content).and_then(|_| file.sync_all()))
+        .and_then(|_| fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use serde_json::json;
+
+    use std::sync::Arc;
+
+    use crate::cache::{write_atomically, CachedPayload, FeatureCache, FileFeatureCache, InMemoryFeatureCache, NamespacedFeatureCache};
+
+    fn temp_cache_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("growthbook-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[tokio::test]
+    async fn test_file_cache_set_and_get() {
+        let directory = temp_cache_directory("file-cache");
+        let cache = FileFeatureCache::new(&directory);
+        assert_eq!(cache.get("sdk-abc").await.unwrap(), None);
+
+        let payload = CachedPayload::now(json!({"features": {}, "encryptedFeatures": "abc==.def"}));
+        cache.set("sdk-abc", payload.clone()).await.unwrap();
+        assert_eq!(cache.get("sdk-abc").await.unwrap(), Some(payload));
+        assert_eq!(cache.get("sdk-other").await.unwrap(), None);
+
+        // nothing but the cache file itself is left behind
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 1);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_file_cache_corrupt_file_is_an_error() {
+        let directory = temp_cache_directory("corrupt-cache");
+        let cache = FileFeatureCache::new(&directory);
+        fs::write(cache.path_for("sdk-abc"), "{\"savedAt\": 1, \"payl").unwrap();
+        assert!(cache.get("sdk-abc").await.is_err());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_writes_never_publish_a_torn_file() {
+        let directory = temp_cache_directory("concurrent-writes");
+        let path = directory.join("document.json");
+        let writers: Vec<_> = (0..8u8)
+            .map(|writer| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let content = vec![b'a' + writer; 64 * 1024];
+                    for _ in 0..20 {
+                        write_atomically(&path, &content).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let content = fs::read(&path).unwrap();
+        assert_eq!(content.len(), 64 * 1024);
+        assert!(content.iter().all(|byte| *byte == content[0]));
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 1);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_file_cache_path_is_encoded() {
+        let cache = FileFeatureCache::new("/tmp");
+        assert_eq!(cache.path_for("sdk-abc_1"), PathBuf::from("/tmp/growthbook-sdk-abc_1.json"));
+        assert_eq!(
+            cache.path_for("../etc/passwd"),
+            PathBuf::from("/tmp/growthbook-%2E%2E%2Fetc%2Fpasswd.json")
+        );
+        assert_ne!(cache.path_for("sdk.abc"), cache.path_for("sdk_abc"));
+        assert_eq!(cache.path_for("sdk-Abc"), PathBuf::from("/tmp/growthbook-sdk-%41bc.json"));
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_cache_is_shared_between_clones() {
+        let cache = InMemoryFeatureCache::default();
+        let clone = cache.clone();
+        let payload = CachedPayload::now(json!({"features": {}}));
+        cache.set("sdk-abc", payload.clone()).await.unwrap();
+        assert_eq!(clone.get("sdk-abc").await.unwrap(), Some(payload));
+    }
+
+    #[tokio::test]
+    async fn test_namespaced_caches_keep_equal_keys_apart() {
+        let cache = Arc::new(InMemoryFeatureCache::default());
+        let first = NamespacedFeatureCache::new("https://cdn.growthbook.io", cache.clone());
+        let second = NamespacedFeatureCache::new("https://growthbook.example.com", cache.clone());
+        let payload = CachedPayload::now(json!({"features": {}}));
+        first.set("sdk-abc", payload.clone()).await.unwrap();
+        assert_eq!(first.get("sdk-abc").await.unwrap(), Some(payload));
+        assert_eq!(second.get("sdk-abc").await.unwrap(), None);
+        assert_eq!(cache.get("sdk-abc").await.unwrap(), None);
+    }
+}
@@ -2,29 +2,29 @@ use log::error;
 use regex::Regex;
 use serde_json::Value;
 
-use crate::model::{Attributes, Condition};
+use crate::model::{Attributes, Condition, SavedGroups};
 
-pub fn eval_condition(attributes: &Attributes, condition: &Condition) -> bool {
+pub fn eval_condition(attributes: &Attributes, condition: &Condition, saved_groups: &SavedGroups) -> bool {
     if let Some(or_condition) = condition.get("$or") {
-        return eval_or(attributes, or_condition);
+        return eval_or(attributes, or_condition, saved_groups);
     }
 
     if let Some(nor_condition) = condition.get("$nor") {
-        return !eval_or(attributes, nor_condition);
+        return !eval_or(attributes, nor_condition, saved_groups);
     }
 
     if let Some(and_condition) = condition.get("$and") {
-        return eval_and(attributes, and_condition);
+        return eval_and(attributes, and_condition, saved_groups);
     }
 
     if let Some(not_condition) = condition.get("$not") {
-        return !eval_condition(attributes, not_condition);
+        return !eval_condition(attributes, not_condition, saved_groups);
     }
 
     if let Some(obj) = condition.as_object() {
         for (key, value) in obj.iter() {
             let attribute_value = get_path(attributes, key);
-            if !eval_condition_value(value, attribute_value) {
+            if !eval_condition_value(value, attribute_value, saved_groups) {
                 return false;
             }
         }
@@ -33,26 +33,28 @@ pub fn eval_condition(attributes: &Attributes, condition: &Condition) -> bool {
     true
 }
 
-fn eval_or(attributes: &Attributes, conditions: &Condition) -> bool {
+fn eval_or(attributes: &Attributes, conditions: &Condition, saved_groups: &SavedGroups) -> bool {
     if let Some(array) = conditions.as_array() {
-        return array.is_empty() || array.iter().any(|condition| eval_condition(attributes, condition));
+        array.is_empty() || array.iter().any(|condition| eval_condition(attributes, condition, saved_groups))
     } else {
         true
     }
 }
 
-fn eval_and(attributes: &Attributes, conditions: &Condition) -> bool {
+fn eval_and(attributes: &Attributes, conditions: &Condition, saved_groups: &SavedGroups) -> bool {
     if let Some(array) = conditions.as_array() {
-        return array.iter().all(|condition| eval_condition(attributes, condition));
+        array.iter().all(|condition| eval_condition(attributes, condition, saved_groups))
     } else {
         false
     }
 }
 
-fn eval_condition_value(condition_value: &Value, attribute_value: Option<&Value>) -> bool {
+fn eval_condition_value(condition_value: &Value, attribute_value: Option<&Value>, saved_groups: &SavedGroups) -> bool {
     if let Some(obj) = condition_value.as_object() {
         if is_operator_object(condition_value) {
-            return obj.iter().all(|(key, value)| eval_operator_condition(key, attribute_value, value));
+            return obj
+                .iter()
+                .all(|(key, value)| eval_operator_condition(key, attribute_value, value, saved_groups));
         }
     }
 
@@ -88,7 +90,8 @@ fn get_type(attribute_value: Option<&Value>) -> &str {
     }
 }
 
-fn get_path<'a>(attributes: &'a Attributes, key: &'a str) -> Option<&'a Value> {
+// resolves dotted paths like `company.id` into nested attributes
+pub fn get_path<'a>(attributes: &'a Attributes, key: &str) -> Option<&'a Value> {
     let fields: Vec<&str> = key.split('.').collect();
     let mut current_value = attributes;
 
@@ -103,13 +106,13 @@ fn get_path<'a>(attributes: &'a Attributes, key: &'a str) -> Option<&'a Value> {
     Some(current_value)
 }
 
-fn elem_match(condition_value: &Value, attribute_value: Option<&Value>) -> bool {
+fn elem_match(condition_value: &Value, attribute_value: Option<&Value>, saved_groups: &SavedGroups) -> bool {
     if let Some(attribute_array) = attribute_value.and_then(Value::as_array) {
         attribute_array.iter().any(|attribute| {
             if is_operator_object(condition_value) {
-                eval_condition_value(condition_value, Some(attribute))
+                eval_condition_value(condition_value, Some(attribute), saved_groups)
             } else {
-                eval_condition(attribute, condition_value)
+                eval_condition(attribute, condition_value, saved_groups)
             }
         })
     } else {
@@ -167,6 +170,15 @@ fn is_in(condition_value: &Value, attribute_value: Option<&Value>) -> bool {
     }
 }
 
+fn is_in_group(condition_value: &Value, attribute_value: Option<&Value>, saved_groups: &SavedGroups) -> bool {
+    let group = condition_value.as_str().and_then(|group_id| saved_groups.get(group_id));
+    match (group, attribute_value) {
+        (Some(group), Some(Value::Array(values))) => values.iter().any(|value| group.contains(value)),
+        (Some(group), Some(value)) => group.contains(value),
+        _ => false,
+    }
+}
+
 pub(crate) fn compare_values(attribute_value: Option<&Value>, condition_value: &Value, operator: &str) -> bool {
     if let Some(attribute_value) = attribute_value {
         match (attribute_value, condition_value) {
@@ -205,7 +217,7 @@ pub(crate) fn compare_values(attribute_value: Option<&Value>, condition_value: &
     }
 }
 
-pub(crate) fn eval_operator_condition(operator: &str, attribute_value: Option<&Value>, condition_value: &Value) -> bool {
+pub(crate) fn eval_operator_condition(operator: &str, attribute_value: Option<&Value>, condition_value: &Value, saved_groups: &SavedGroups) -> bool {
     match operator {
         "$eq" => compare_values(attribute_value, condition_value, "=="),
         "$ne" => compare_values(attribute_value, condition_value, "!="),
@@ -237,24 +249,29 @@ pub(crate) fn eval_operator_condition(operator: &str, attribute_value: Option<&V
         }
         "$all" => {
             if let (Some(attribute_value), Some(condition_value)) = (attribute_value.and_then(Value::as_array), condition_value.as_array()) {
-                condition_value
-                    .iter()
-                    .all(|condition| attribute_value.iter().any(|attribute| eval_condition_value(condition, Some(attribute))))
+                condition_value.iter().all(|condition| {
+                    attribute_value
+                        .iter()
+                        .any(|attribute| eval_condition_value(condition, Some(attribute), saved_groups))
+                })
             } else {
                 false
             }
         }
-        "$elemMatch" => elem_match(condition_value, attribute_value),
+        "$elemMatch" => elem_match(condition_value, attribute_value, saved_groups),
         "$size" => {
             if let Some(attribute_value) = attribute_value.and_then(Value::as_array) {
-                eval_condition_value(condition_value, Some(&Value::from(attribute_value.len())))
+                eval_condition_value(condition_value, Some(&Value::from(attribute_value.len())), saved_groups)
             } else {
                 false
             }
         }
-        "$exists" => attribute_value.map_or(false, |attr| !attr.is_null()) == condition_value.as_bool().unwrap_or(false),
+        "$exists" => attribute_value.is_some_and(|attr| !attr.is_null()) == condition_value.as_bool().unwrap_or(false),
         "$type" => get_type(attribute_value) == condition_value.as_str().unwrap_or(""),
-        "$not" => !eval_condition_value(condition_value, attribute_value),
+        "$not" => !eval_condition_value(condition_value, attribute_value, saved_groups),
+        // an unknown group is treated as empty
+        "$inGroup" => is_in_group(condition_value, attribute_value, saved_groups),
+        "$notInGroup" => !is_in_group(condition_value, attribute_value, saved_groups),
         "$veq" => padded_version_string(attribute_value.and_then(Value::as_str)) == padded_version_string(condition_value.as_str()),
         "$vne" => padded_version_string(attribute_value.and_then(Value::as_str)) != padded_version_string(condition_value.as_str()),
         "$vgt" => padded_version_string(attribute_value.and_then(Value::as_str)) > padded_version_string(condition_value.as_str()),
@@ -267,8 +284,8 @@ pub(crate) fn eval_operator_condition(operator: &str, attribute_value: Option<&V
 
 #[cfg(test)]
 mod tests {
-    use crate::condition::compare_values;
-    use crate::model::BucketRange;
+    use crate::condition::{compare_values, eval_condition};
+    use crate::model::{BucketRange, SavedGroups};
     use serde_json::json;
     use std::f32::consts::PI;
 
@@ -310,4 +327,36 @@ mod tests {
         assert!(compare_values(Some(&json!("something")), &json!("SOMETHING"), ">"));
         assert!(!compare_values(Some(&json!("something")), &json!("SOMETHING"), "<"));
     }
+
+    #[test]
+    fn test_in_group() {
+        let saved_groups: SavedGroups = serde_json::from_value(json!({"beta_testers": ["user-1", "user-2", 3]})).unwrap();
+        let in_group = json!({"id": {"$inGroup": "beta_testers"}});
+        let not_in_group = json!({"id": {"$notInGroup": "beta_testers"}});
+
+        assert!(eval_condition(&json!({"id": "user-1"}), &in_group, &saved_groups));
+        assert!(eval_condition(&json!({"id": 3}), &in_group, &saved_groups));
+        assert!(eval_condition(&json!({"id": ["user-9", "user-2"]}), &in_group, &saved_groups));
+        assert!(!eval_condition(&json!({"id": "user-9"}), &in_group, &saved_groups));
+        assert!(!eval_condition(&json!({}), &in_group, &saved_groups));
+
+        assert!(!eval_condition(&json!({"id": "user-1"}), &not_in_group, &saved_groups));
+        assert!(eval_condition(&json!({"id": "user-9"}), &not_in_group, &saved_groups));
+        assert!(eval_condition(&json!({}), &not_in_group, &saved_groups));
+    }
+
+    #[test]
+    fn test_unknown_group_is_empty() {
+        let saved_groups = SavedGroups::default();
+        assert!(!eval_condition(
+            &json!({"id": "user-1"}),
+            &json!({"id": {"$inGroup": "missing"}}),
+            &saved_groups
+        ));
+        assert!(eval_condition(
+            &json!({"id": "user-1"}),
+            &json!({"id": {"$notInGroup": "missing"}}),
+            &saved_groups
+        ));
+    }
 }
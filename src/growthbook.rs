@@ -1,11 +1,13 @@
 use std::collections::HashMap;
 
 use chrono::Local;
-use serde_json::Value;
+use log::error;
+use serde_json::{json, Value};
 
-use crate::condition::eval_condition;
+use crate::condition::{eval_condition, get_path};
 use crate::model::Source::Experiment as EnumExperiment;
-use crate::model::{BucketRange, Context, Experiment, ExperimentResult, Feature, FeatureResult, Filter, Source, TrackingCallback};
+use crate::model::{BucketRange, Context, Experiment, ExperimentResult, Feature, FeatureResult, Filter, ParentCondition, Source, TrackingCallback};
+use crate::sticky_bucket::{sticky_experiment_key, StickyAssignmentsDocument, StickyBucketService};
 use crate::util;
 use crate::util::{choose_variation, in_range};
 
@@ -47,7 +49,7 @@ impl GrowthBook {
     }
 
     pub fn subscribe(&mut self, callback: TrackingCallback) -> i64 {
-        let subscription_id = Local::now().timestamp_nanos();
+        let subscription_id = Local::now().timestamp_nanos_opt().unwrap_or_default();
         self.subscriptions.insert(subscription_id, callback);
         subscription_id
     }
@@ -63,13 +65,11 @@ impl GrowthBook {
     fn is_filtered_out(&self, filters: &Vec<Filter>) -> bool {
         for filter in filters {
             let hash_attribute = &filter.attribute;
-            let hash_value = self
-                .context
-                .attributes
-                .get(hash_attribute)
-                .map_or("", |value| value.as_str().unwrap_or(""));
+            let hash_value = get_path(&self.context.attributes, hash_attribute)
+                .map(util::hash_value_string)
+                .unwrap_or_default();
 
-            let n = util::hash(&filter.seed, hash_value, filter.hash_version);
+            let n = util::hash(&filter.seed, &hash_value, filter.hash_version);
 
             if let Some(n_value) = n {
                 if !filter.ranges.iter().any(|filter_range| in_range(n_value, filter_range)) {
@@ -85,6 +85,7 @@ impl GrowthBook {
         &self,
         seed: &str,
         hash_attribute: &Option<String>,
+        fallback_attribute: &Option<String>,
         range: &Option<BucketRange>,
         coverage: &Option<f32>,
         hash_version: &Option<i32>,
@@ -93,19 +94,15 @@ impl GrowthBook {
             return true;
         }
 
-        let hash_attribute = hash_attribute.as_deref().unwrap_or("id");
         let hash_version = hash_version.unwrap_or(1);
-        let hash_value = self
-            .context
-            .attributes
-            .get(hash_attribute)
-            .map_or("", |value| value.as_str().unwrap_or(""));
+        let (_, hash_value) = self.get_hash_attribute(hash_attribute, fallback_attribute);
+        let hash_value = util::hash_value_string(&hash_value);
 
         if hash_value.is_empty() {
             return false;
         }
 
-        if let Some(n_value) = util::hash(seed, hash_value, hash_version) {
+        if let Some(n_value) = util::hash(seed, &hash_value, hash_version) {
             if let Some(range_value) = range {
                 return in_range(n_value, range_value);
             }
@@ -132,12 +129,7 @@ impl GrowthBook {
             variation_index = 0;
             in_experiment = false;
         }
-        let hash_attribute = match &experiment.hash_attribute {
-            Some(hash_attribute) => hash_attribute,
-            None => "id",
-        };
-        let empty_string_value: Value = Value::String(String::new());
-        let hash_value = self.context.attributes.get(hash_attribute).unwrap_or(&empty_string_value);
+        let (hash_attribute, hash_value) = self.get_hash_attribute(&experiment.hash_attribute, &experiment.fallback_attribute);
 
         let meta = experiment.meta.get(variation_index as usize);
         ExperimentResult {
@@ -145,25 +137,46 @@ impl GrowthBook {
             variation_id: variation_index,
             value: experiment.variations.get(variation_index as usize).unwrap_or(&Value::Null).clone(),
             hash_used: hash_used.unwrap_or(false),
-            hash_attribute: hash_attribute.to_owned(),
-            hash_value: hash_value.clone(),
+            hash_attribute,
+            hash_value,
             feature_id: feature_id.map(|f| f.to_owned()),
-            key: meta.and_then(|m| m.key.clone()).unwrap_or(variation_index.to_string()),
+            key: variation_key(experiment, variation_index as usize),
             bucket: bucket.unwrap_or(0.0),
             name: meta.and_then(|m| m.name.clone()),
             passthrough: meta.and_then(|m| m.passthrough).unwrap_or(false),
+            sticky_bucket_used: false,
         }
     }
 
     pub fn eval_feature(&self, key: &str) -> FeatureResult {
+        self.eval_feature_with_stack(key, &mut vec![])
+    }
+
+    // `stack` holds the features whose prerequisites are being evaluated, meeting one of them again is a cycle
+    fn eval_feature_with_stack(&self, key: &str, stack: &mut Vec<String>) -> FeatureResult {
         if !self.context.features.contains_key(key) {
             return self.get_feature_result(Value::Null, Source::UnknownFeature, None, None);
         }
+        if stack.iter().any(|id| id == key) {
+            return self.get_feature_result(Value::Null, Source::CyclicPrerequisite, None, None);
+        }
+        stack.push(key.to_string());
+        let result = self.eval_feature_rules(key, stack);
+        stack.pop();
+        result
+    }
+
+    fn eval_feature_rules(&self, key: &str, stack: &mut Vec<String>) -> FeatureResult {
         let default_feature = Feature::default();
         let feature = self.context.features.get(key).unwrap_or(&default_feature);
         for rule in feature.rules.iter() {
+            match self.eval_parent_conditions(&rule.parent_conditions, stack) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(source) => return self.get_feature_result(Value::Null, source, None, None),
+            }
             if let Some(condition) = &rule.condition {
-                if !eval_condition(&self.context.attributes, condition) {
+                if !eval_condition(&self.context.attributes, condition, &self.context.saved_groups) {
                     continue;
                 }
             }
@@ -178,6 +191,7 @@ impl GrowthBook {
                 if !self.is_included_in_rollout(
                     seed,
                     &rule.hash_attribute.clone(),
+                    &rule.fallback_attribute.clone(),
                     &rule.range.clone(),
                     &rule.coverage.clone(),
                     &rule.hash_version.clone(),
@@ -205,7 +219,12 @@ impl GrowthBook {
                 name: rule.name.clone(),
                 phase: rule.phase.clone(),
                 hash_attribute: rule.hash_attribute.clone(),
+                fallback_attribute: rule.fallback_attribute.clone(),
                 hash_version: rule.hash_version,
+                bucket_version: rule.bucket_version,
+                min_bucket_version: rule.min_bucket_version,
+                blocked_variations: rule.blocked_variations.clone(),
+                disable_sticky_bucketing: rule.disable_sticky_bucketing,
                 ..Experiment::default()
             };
             let result: ExperimentResult = self.run_internal(&experiment, Some(key));
@@ -218,6 +237,26 @@ impl GrowthBook {
         }
         self.get_feature_result(feature.default_value.clone().unwrap_or(Value::Null), Source::DefaultValue, None, None)
     }
+
+    // Ok(false) when a parent condition fails, Err with the source the whole feature resolves to
+    // when a gate fails or the prerequisites form a cycle
+    fn eval_parent_conditions(&self, parent_conditions: &[ParentCondition], stack: &mut Vec<String>) -> Result<bool, Source> {
+        for parent_condition in parent_conditions {
+            let parent = self.eval_feature_with_stack(&parent_condition.id, stack);
+            if parent.source == Source::CyclicPrerequisite {
+                return Err(Source::CyclicPrerequisite);
+            }
+            let parent_value = json!({ "value": parent.value });
+            if !eval_condition(&parent_value, &parent_condition.condition, &self.context.saved_groups) {
+                if parent_condition.gate {
+                    return Err(Source::Prerequisite);
+                }
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
     pub fn run(&self, experiment: &Experiment) -> ExperimentResult {
         let result = self.run_internal(experiment, None);
         self.subscriptions.iter().for_each(|(_k, v)| {
@@ -226,6 +265,16 @@ impl GrowthBook {
         result
     }
 
+    // runs the context's experiments list against the current url, manual experiments are left to run()
+    pub fn run_auto_experiments(&self) -> Vec<(&Experiment, ExperimentResult)> {
+        self.context
+            .experiments
+            .iter()
+            .filter(|experiment| !experiment.manual.unwrap_or(false))
+            .map(|experiment| (experiment, self.run(experiment)))
+            .collect()
+    }
+
     fn run_internal(&self, experiment: &Experiment, id: Option<&str>) -> ExperimentResult {
         if experiment.variations.len() < 2 || !self.context.enabled {
             return self.get_experiment_result(experiment, None, None, id, None);
@@ -245,36 +294,44 @@ impl GrowthBook {
                 return self.get_experiment_result(experiment, None, None, id, None);
             }
         }
-        let hash_attribute = match &experiment.hash_attribute {
-            Some(hash_attribute) => hash_attribute,
-            None => "id",
-        };
-
-        let empty_string_value: Value = Value::String(String::new());
-        let hash_value = self.context.attributes.get(hash_attribute).unwrap_or(&empty_string_value);
-        let hash_value_string = hash_value
-            .as_i64()
-            .map(|primitive| primitive.to_string())
-            .unwrap_or_else(|| hash_value.as_str().unwrap_or("").to_string());
+        let (hash_attribute, hash_value) = self.get_hash_attribute(&experiment.hash_attribute, &experiment.fallback_attribute);
+        let hash_value_string = util::hash_value_string(&hash_value);
         if hash_value_string.is_empty() {
             return self.get_experiment_result(experiment, None, None, id, None);
         }
 
-        if !experiment.filters.is_empty() {
-            if self.is_filtered_out(&experiment.filters) {
-                return self.get_experiment_result(experiment, None, None, id, None);
-            }
-        } else if let Some(ns) = &experiment.namespace {
-            if !ns.id.is_empty() && !util::in_namespace(&hash_value_string, ns) {
-                return self.get_experiment_result(experiment, None, None, id, None);
+        let sticky_bucket_service = self.sticky_bucket_service(experiment);
+        let mut sticky_document = sticky_bucket_service.and_then(|service| self.get_sticky_document(service, &hash_attribute, &hash_value_string));
+        let sticky_bucket = match (sticky_bucket_service, &sticky_document) {
+            (Some(service), Some(document)) => get_sticky_bucket(experiment, &self.get_sticky_assignments(service, experiment, document)),
+            _ => StickyBucket::Unassigned,
+        };
+
+        // a stored assignment keeps the user in the experiment when filters or namespaces changed since,
+        // targeting conditions and prerequisites still apply
+        if !matches!(sticky_bucket, StickyBucket::Assigned(_)) {
+            if !experiment.filters.is_empty() {
+                if self.is_filtered_out(&experiment.filters) {
+                    return self.get_experiment_result(experiment, None, None, id, None);
+                }
+            } else if let Some(ns) = &experiment.namespace {
+                if !ns.id.is_empty() && !util::in_namespace(&hash_value_string, ns) {
+                    return self.get_experiment_result(experiment, None, None, id, None);
+                }
             }
         }
 
         if let Some(c) = &experiment.condition {
-            if !eval_condition(&self.context.attributes, c) {
+            if !eval_condition(&self.context.attributes, c, &self.context.saved_groups) {
                 return self.get_experiment_result(experiment, None, None, id, None);
             }
         }
+        if !matches!(self.eval_parent_conditions(&experiment.parent_conditions, &mut vec![]), Ok(true)) {
+            return self.get_experiment_result(experiment, None, None, id, None);
+        }
+        if !experiment.url_patterns.is_empty() && !util::is_url_targeted(&self.context.url, &experiment.url_patterns) {
+            return self.get_experiment_result(experiment, None, None, id, None);
+        }
         let ranges = match !experiment.ranges.is_empty() {
             true => experiment.ranges.clone(),
             false => util::get_bucket_ranges(
@@ -288,7 +345,16 @@ impl GrowthBook {
             &hash_value_string,
             experiment.hash_version.unwrap_or(1),
         );
-        let assigned = choose_variation(n.unwrap_or(1.0), &ranges);
+        let (assigned, sticky_bucket_used) = match sticky_bucket {
+            StickyBucket::Assigned(variation) => (variation, true),
+            StickyBucket::Blocked => {
+                return ExperimentResult {
+                    sticky_bucket_used: true,
+                    ..self.get_experiment_result(experiment, None, None, id, None)
+                };
+            }
+            StickyBucket::Unassigned => (choose_variation(n.unwrap_or(1.0), &ranges), false),
+        };
 
         if assigned == -1 {
             return self.get_experiment_result(experiment, None, None, id, None);
@@ -301,13 +367,88 @@ impl GrowthBook {
             return self.get_experiment_result(experiment, None, None, id, None);
         }
 
-        let result = self.get_experiment_result(experiment, Some(assigned), Some(true), id, n);
+        let result = ExperimentResult {
+            sticky_bucket_used,
+            ..self.get_experiment_result(experiment, Some(assigned), Some(true), id, n)
+        };
+        if let (Some(service), Some(document)) = (sticky_bucket_service, sticky_document.as_mut()) {
+            let experiment_key = sticky_experiment_key(&experiment.key, experiment.bucket_version.unwrap_or(0));
+            if document.assignments.get(&experiment_key) != Some(&result.key) {
+                document.assignments.insert(experiment_key, result.key.clone());
+                if let Err(e) = service.save_assignments(document) {
+                    error!("Error saving sticky buckets: {}", e);
+                }
+            }
+        }
         if let Some(tc) = &self.tracking_callback {
             (tc.0)(experiment, &result);
         }
         result
     }
 
+    fn sticky_bucket_service(&self, experiment: &Experiment) -> Option<&dyn StickyBucketService> {
+        if experiment.disable_sticky_bucketing.unwrap_or(false) {
+            return None;
+        }
+        self.context.sticky_bucket_service.as_deref()
+    }
+
+    // None when the service fails, so a read error never ends in overwriting the stored assignments
+    fn get_sticky_document(
+        &self,
+        service: &dyn StickyBucketService,
+        attribute_name: &str,
+        attribute_value: &str,
+    ) -> Option<StickyAssignmentsDocument> {
+        match service.get_assignments(attribute_name, attribute_value) {
+            Ok(document) => Some(document.unwrap_or_else(|| StickyAssignmentsDocument::new(attribute_name, attribute_value))),
+            Err(e) => {
+                error!("Error reading sticky buckets: {}", e);
+                None
+            }
+        }
+    }
+
+    // assignments stored for the fallback attribute count as well, so a user keeps their variation once
+    // the primary attribute shows up (e.g. after logging in). the primary attribute's assignments win
+    fn get_sticky_assignments(
+        &self,
+        service: &dyn StickyBucketService,
+        experiment: &Experiment,
+        document: &StickyAssignmentsDocument,
+    ) -> HashMap<String, String> {
+        let mut assignments = HashMap::new();
+        if let Some(fallback_attribute) = &experiment.fallback_attribute {
+            let fallback_value = get_path(&self.context.attributes, fallback_attribute)
+                .map(util::hash_value_string)
+                .unwrap_or_default();
+            if *fallback_attribute != document.attribute_name && !fallback_value.is_empty() {
+                if let Some(fallback_document) = self.get_sticky_document(service, fallback_attribute, &fallback_value) {
+                    assignments.extend(fallback_document.assignments);
+                }
+            }
+        }
+        assignments.extend(document.assignments.clone());
+        assignments
+    }
+
+    // the attribute to hash on and its value, the fallback attribute is used when the primary one is missing or empty.
+    // both can be dotted paths into nested attributes, like in conditions
+    fn get_hash_attribute(&self, hash_attribute: &Option<String>, fallback_attribute: &Option<String>) -> (String, Value) {
+        let hash_attribute = hash_attribute.as_deref().unwrap_or("id");
+        let hash_value = get_path(&self.context.attributes, hash_attribute);
+        if hash_value.map(util::hash_value_string).unwrap_or_default().is_empty() {
+            if let Some(fallback_attribute) = fallback_attribute {
+                if let Some(fallback_value) = get_path(&self.context.attributes, fallback_attribute) {
+                    if !util::hash_value_string(fallback_value).is_empty() {
+                        return (fallback_attribute.clone(), fallback_value.clone());
+                    }
+                }
+            }
+        }
+        (hash_attribute.to_string(), hash_value.cloned().unwrap_or(Value::String(String::new())))
+    }
+
     pub fn is_on(&self, key: &str) -> bool {
         self.eval_feature(key).on
     }
@@ -351,12 +492,54 @@ impl GrowthBook {
     }
 }
 
+enum StickyBucket {
+    Unassigned,
+    Assigned(i32),
+    // holds an assignment from before min_bucket_version, or to a blocked variation
+    Blocked,
+}
+
+fn get_sticky_bucket(experiment: &Experiment, assignments: &HashMap<String, String>) -> StickyBucket {
+    let min_bucket_version = experiment.min_bucket_version.unwrap_or(0);
+    if (0..min_bucket_version).any(|version| assignments.contains_key(&sticky_experiment_key(&experiment.key, version))) {
+        return StickyBucket::Blocked;
+    }
+    let assigned_key = match assignments.get(&sticky_experiment_key(&experiment.key, experiment.bucket_version.unwrap_or(0))) {
+        Some(assigned_key) => assigned_key,
+        None => return StickyBucket::Unassigned,
+    };
+    match (0..experiment.variations.len()).find(|index| variation_key(experiment, *index) == *assigned_key) {
+        Some(index) if experiment.blocked_variations.contains(&(index as i32)) => StickyBucket::Blocked,
+        Some(index) => StickyBucket::Assigned(index as i32),
+        // the variation is gone, bucket the user again
+        None => StickyBucket::Unassigned,
+    }
+}
+
+fn variation_key(experiment: &Experiment, index: usize) -> String {
+    experiment.meta.get(index).and_then(|meta| meta.key.clone()).unwrap_or(index.to_string())
+}
+
 #[cfg(test)]
 mod tests {
-    use serde_json::json;
+    use std::sync::Arc;
+
+    use serde_json::{json, Value};
 
     use crate::growthbook::GrowthBook;
-    use crate::model::{Context, Experiment, TrackingCallback};
+    use crate::model::{Context, Experiment, Namespace, Source, TrackingCallback, VariationMeta};
+    use crate::sticky_bucket::{InMemoryStickyBucketService, StickyBucketService};
+
+    fn growthbook_with_features(features: Value, attributes: Value) -> GrowthBook {
+        GrowthBook {
+            context: Context {
+                attributes,
+                features: Arc::new(serde_json::from_value(features).unwrap()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
 
     #[test]
     fn test_tracking_callback_called() {
@@ -545,4 +728,473 @@ mod tests {
         });
         assert_eq!(unsafe { COUNT }, 3);
     }
+
+    #[test]
+    fn test_auto_experiments_respect_url_targeting() {
+        let experiments: Vec<Experiment> = serde_json::from_value(json!([
+            {
+                "key": "pricing-redirect",
+                "changeId": "change-1",
+                "urlPatterns": [{"type": "simple", "include": true, "pattern": "/pricing"}],
+                "variations": [{}, {"urlRedirect": "https://example.com/pricing-v2"}],
+                "weights": [0.0, 1.0]
+            },
+            {
+                "key": "manual-banner",
+                "manual": true,
+                "variations": [{}, {"css": "body { color: red; }"}]
+            }
+        ]))
+        .unwrap();
+        assert_eq!(
+            experiments[0].changes()[1].url_redirect,
+            Some("https://example.com/pricing-v2".to_string())
+        );
+
+        let gb = GrowthBook {
+            context: Context {
+                attributes: json!({ "id": "1" }),
+                url: "https://example.com/pricing".to_string(),
+                experiments: experiments.clone(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let results = gb.run_auto_experiments();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0.key, "pricing-redirect");
+        assert!(results[0].1.in_experiment);
+        assert_eq!(results[0].1.variation_id, 1);
+
+        let gb = GrowthBook {
+            context: Context {
+                attributes: json!({ "id": "1" }),
+                url: "https://example.com/about".to_string(),
+                experiments,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(!gb.run_auto_experiments()[0].1.in_experiment);
+    }
+
+    #[test]
+    fn test_prerequisites() {
+        let features = json!({
+            "payments-v2": {
+                "defaultValue": false,
+                "rules": [{"condition": {"country": "US"}, "force": true}]
+            },
+            "new-checkout": {
+                "defaultValue": "old",
+                "rules": [{
+                    "parentConditions": [{"id": "payments-v2", "condition": {"value": true}}],
+                    "force": "new"
+                }]
+            },
+            "checkout-banner": {
+                "defaultValue": true,
+                "rules": [{
+                    "parentConditions": [{"id": "payments-v2", "condition": {"value": true}, "gate": true}]
+                }]
+            }
+        });
+
+        let gb = growthbook_with_features(features.clone(), json!({ "id": "1", "country": "US" }));
+        assert_eq!(gb.eval_feature("new-checkout").value, json!("new"));
+        let banner = gb.eval_feature("checkout-banner");
+        assert_eq!(banner.value, json!(true));
+        assert_eq!(banner.source, Source::DefaultValue);
+
+        let gb = growthbook_with_features(features, json!({ "id": "1", "country": "DE" }));
+        let checkout = gb.eval_feature("new-checkout");
+        assert_eq!(checkout.value, json!("old"));
+        assert_eq!(checkout.source, Source::DefaultValue);
+        let banner = gb.eval_feature("checkout-banner");
+        assert_eq!(banner.value, Value::Null);
+        assert_eq!(banner.source, Source::Prerequisite);
+        assert!(banner.off);
+    }
+
+    #[test]
+    fn test_cyclic_prerequisites() {
+        let gb = growthbook_with_features(
+            json!({
+                "a": {
+                    "defaultValue": 1,
+                    "rules": [{"parentConditions": [{"id": "b", "condition": {"value": {"$exists": true}}}], "force": 2}]
+                },
+                "b": {
+                    "defaultValue": 1,
+                    "rules": [{"parentConditions": [{"id": "a", "condition": {"value": {"$exists": true}}}], "force": 2}]
+                },
+                "c": {
+                    "defaultValue": 1,
+                    "rules": [{"parentConditions": [{"id": "a", "condition": {"value": 1}}], "force": 2}]
+                }
+            }),
+            json!({ "id": "1" }),
+        );
+        for key in ["a", "b", "c"] {
+            let result = gb.eval_feature(key);
+            assert_eq!(result.value, Value::Null, "feature {}", key);
+            assert_eq!(result.source, Source::CyclicPrerequisite, "feature {}", key);
+        }
+    }
+
+    #[test]
+    fn test_experiment_parent_conditions() {
+        let gb = growthbook_with_features(json!({ "payments-v2": {"defaultValue": true} }), json!({ "id": "1" }));
+        let experiment: Experiment = serde_json::from_value(json!({
+            "key": "checkout-copy",
+            "variations": [0, 1],
+            "parentConditions": [{"id": "payments-v2", "condition": {"value": true}}]
+        }))
+        .unwrap();
+        assert!(gb.run(&experiment).in_experiment);
+
+        let gb = growthbook_with_features(json!({ "payments-v2": {"defaultValue": false} }), json!({ "id": "1" }));
+        assert!(!gb.run(&experiment).in_experiment);
+    }
+
+    #[test]
+    fn test_sticky_bucketing() {
+        let service = InMemoryStickyBucketService::default();
+        let gb = GrowthBook {
+            context: Context {
+                attributes: json!({ "id": "1" }),
+                sticky_bucket_service: Some(Arc::new(service.clone())),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let experiment = Experiment {
+            key: "checkout".to_string(),
+            variations: vec![json!("control"), json!("new")],
+            weights: vec![1.0, 0.0],
+            meta: vec![
+                VariationMeta {
+                    key: Some("control".to_string()),
+                    ..Default::default()
+                },
+                VariationMeta {
+                    key: Some("new".to_string()),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let result = gb.run(&experiment);
+        assert_eq!(result.value, json!("control"));
+        assert!(!result.sticky_bucket_used);
+        assert_eq!(service.get_assignments("id", "1").unwrap().unwrap().assignments["checkout__0"], "control");
+
+        // the weights moved everyone to "new", but the user stays where they were bucketed
+        let reweighted = Experiment {
+            weights: vec![0.0, 1.0],
+            ..experiment.clone()
+        };
+        let result = gb.run(&reweighted);
+        assert_eq!(result.value, json!("control"));
+        assert!(result.in_experiment);
+        assert!(result.sticky_bucket_used);
+
+        // a namespace the user is outside of no longer matters to them, a targeting condition still does
+        let namespaced = Experiment {
+            namespace: Some(Namespace {
+                id: "checkout".to_string(),
+                range_start: 0.0,
+                range_end: 0.0,
+            }),
+            ..reweighted.clone()
+        };
+        assert!(gb.run(&namespaced).in_experiment);
+        let targeted = Experiment {
+            condition: Some(json!({ "country": "NL" })),
+            ..reweighted.clone()
+        };
+        assert!(!gb.run(&targeted).in_experiment);
+
+        let unsticky = Experiment {
+            disable_sticky_bucketing: Some(true),
+            ..reweighted.clone()
+        };
+        assert_eq!(gb.run(&unsticky).value, json!("new"));
+
+        let blocked = Experiment {
+            blocked_variations: vec![0],
+            ..reweighted.clone()
+        };
+        let result = gb.run(&blocked);
+        assert!(!result.in_experiment);
+        assert!(result.sticky_bucket_used);
+
+        // blocked variations only shut out users with a stored assignment, fresh users are bucketed as usual
+        let other_user = GrowthBook {
+            context: Context {
+                attributes: json!({ "id": "2" }),
+                sticky_bucket_service: Some(Arc::new(service.clone())),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let result = other_user.run(&Experiment {
+            blocked_variations: vec![1],
+            ..reweighted.clone()
+        });
+        assert_eq!(result.value, json!("new"));
+        assert!(!result.sticky_bucket_used);
+
+        // a new bucket version re-buckets, and min_bucket_version shuts out users from older ones
+        let new_version = Experiment {
+            bucket_version: Some(1),
+            ..reweighted.clone()
+        };
+        let result = gb.run(&new_version);
+        assert_eq!(result.value, json!("new"));
+        assert!(!result.sticky_bucket_used);
+        assert_eq!(service.get_assignments("id", "1").unwrap().unwrap().assignments["checkout__1"], "new");
+
+        let min_version = Experiment {
+            bucket_version: Some(2),
+            min_bucket_version: Some(1),
+            ..reweighted
+        };
+        let result = gb.run(&min_version);
+        assert!(!result.in_experiment);
+        assert!(result.sticky_bucket_used);
+    }
+
+    #[test]
+    fn test_sticky_bucketing_in_feature_rules() {
+        let features = json!({
+            "checkout": {
+                "defaultValue": "off",
+                "rules": [{"key": "checkout-exp", "variations": ["a", "b"], "weights": [1.0, 0.0], "bucketVersion": 3}]
+            }
+        });
+        let service = Arc::new(InMemoryStickyBucketService::default());
+        let mut gb = GrowthBook {
+            context: Context {
+                attributes: json!({ "id": "1" }),
+                features: Arc::new(serde_json::from_value(features).unwrap()),
+                sticky_bucket_service: Some(service.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(gb.eval_feature("checkout").value, json!("a"));
+        assert_eq!(service.get_assignments("id", "1").unwrap().unwrap().assignments["checkout-exp__3"], "0");
+
+        let features = json!({
+            "checkout": {
+                "defaultValue": "off",
+                "rules": [{"key": "checkout-exp", "variations": ["a", "b"], "weights": [0.0, 1.0], "bucketVersion": 3}]
+            }
+        });
+        gb.context.features = Arc::new(serde_json::from_value(features).unwrap());
+        let result = gb.eval_feature("checkout");
+        assert_eq!(result.value, json!("a"));
+        assert!(result.experiment_result.unwrap().sticky_bucket_used);
+    }
+
+    #[test]
+    fn test_fallback_attribute() {
+        let gb = GrowthBook {
+            context: Context {
+                attributes: json!({ "deviceId": "d-123" }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let experiment = Experiment {
+            key: "checkout".to_string(),
+            variations: vec![json!(0), json!(1)],
+            fallback_attribute: Some("deviceId".to_string()),
+            ..Default::default()
+        };
+        let result = gb.run(&experiment);
+        assert!(result.in_experiment);
+        assert_eq!(result.hash_attribute, "deviceId");
+        assert_eq!(result.hash_value, json!("d-123"));
+
+        let result = gb.run(&Experiment {
+            fallback_attribute: None,
+            ..experiment
+        });
+        assert!(!result.in_experiment);
+        assert_eq!(result.hash_attribute, "id");
+
+        let gb = GrowthBook {
+            context: Context {
+                attributes: json!({ "deviceId": "d-123" }),
+                features: Arc::new(
+                    serde_json::from_value(json!({
+                        "rollout": {
+                            "defaultValue": false,
+                            "rules": [{"force": true, "coverage": 1.0, "fallbackAttribute": "deviceId"}]
+                        }
+                    }))
+                    .unwrap(),
+                ),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(gb.eval_feature("rollout").value, json!(true));
+    }
+
+    #[test]
+    fn test_sticky_bucket_follows_user_from_fallback_attribute() {
+        let service = Arc::new(InMemoryStickyBucketService::default());
+        let experiment = Experiment {
+            key: "checkout".to_string(),
+            variations: vec![json!("a"), json!("b")],
+            weights: vec![1.0, 0.0],
+            fallback_attribute: Some("deviceId".to_string()),
+            ..Default::default()
+        };
+        let mut gb = GrowthBook {
+            context: Context {
+                attributes: json!({ "deviceId": "d-123" }),
+                sticky_bucket_service: Some(service.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(gb.run(&experiment).value, json!("a"));
+        assert!(service.get_assignments("deviceId", "d-123").unwrap().is_some());
+
+        // logged in now, and the weights changed in the meantime
+        gb.context.attributes = json!({ "id": "u-1", "deviceId": "d-123" });
+        let result = gb.run(&Experiment {
+            weights: vec![0.0, 1.0],
+            ..experiment
+        });
+        assert_eq!(result.value, json!("a"));
+        assert!(result.sticky_bucket_used);
+        assert_eq!(result.hash_attribute, "id");
+        assert_eq!(service.get_assignments("id", "u-1").unwrap().unwrap().assignments["checkout__0"], "0");
+    }
+
+    #[test]
+    fn test_numeric_hash_attributes() {
+        // [name, feature, expected value], each evaluated for a numeric and a string id
+        let cases = json!([
+            ["rollout with coverage", {"defaultValue": 0, "rules": [{"force": 1, "coverage": 1.0}]}, 1],
+            ["rollout with range", {"defaultValue": 0, "rules": [{"force": 1, "range": [0.0, 1.0]}]}, 1],
+            ["force rule with filter", {"defaultValue": 0, "rules": [{"force": 1, "filters": [{"seed": "seed", "ranges": [[0.5, 1.0]]}]}]}, 1],
+            ["experiment with filter", {"defaultValue": 0, "rules": [{"variations": [1, 1], "filters": [{"seed": "seed", "ranges": [[0.5, 1.0]]}]}]}, 1],
+            ["experiment in namespace", {"defaultValue": 0, "rules": [{"variations": [1, 1], "namespace": ["ns", 0.1, 0.2]}]}, 1]
+        ]);
+        for case in cases.as_array().unwrap() {
+            let case = case.as_array().unwrap();
+            let [numeric, string] = [json!(123), json!("123")].map(|id| {
+                let gb = GrowthBook {
+                    context: Context {
+                        attributes: json!({ "id": id }),
+                        features: Arc::new(serde_json::from_value(json!({ "feature": case[1] })).unwrap()),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                };
+                gb.eval_feature("feature")
+            });
+            assert_eq!(numeric.value, case[2], "case {}", case[0]);
+            assert_eq!(numeric.value, string.value, "case {}", case[0]);
+            assert_eq!(numeric.source, string.source, "case {}", case[0]);
+        }
+
+        let experiment = Experiment {
+            key: "checkout".to_string(),
+            variations: vec![json!(0), json!(1), json!(2), json!(3)],
+            ..Default::default()
+        };
+        let [numeric, string] = [json!(123.0), json!("123")].map(|id| {
+            let gb = GrowthBook {
+                context: Context {
+                    attributes: json!({ "id": id }),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            gb.run(&experiment)
+        });
+        assert!(numeric.in_experiment);
+        assert_eq!(numeric.variation_id, string.variation_id);
+        assert_eq!(numeric.bucket, string.bucket);
+        // falsy ids count as missing, like in the javascript and python sdks
+        let rollout = json!({ "feature": {"defaultValue": 0, "rules": [{"force": 1, "coverage": 1.0}]} });
+        for id in [json!(0), json!(0.0), json!(false), json!("")] {
+            let gb = GrowthBook {
+                context: Context {
+                    attributes: json!({ "id": id }),
+                    features: Arc::new(serde_json::from_value(rollout.clone()).unwrap()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            assert_eq!(gb.eval_feature("feature").value, json!(0), "id {}", id);
+            assert!(!gb.run(&experiment).in_experiment, "id {}", id);
+        }
+    }
+
+    #[test]
+    fn test_nested_hash_attributes() {
+        let nested = json!({ "org": { "id": "org-42" } });
+        let flat = json!({ "orgId": "org-42" });
+        let experiment = Experiment {
+            key: "pricing".to_string(),
+            variations: vec![json!(0), json!(1), json!(2), json!(3)],
+            hash_attribute: Some("org.id".to_string()),
+            ..Default::default()
+        };
+        let nested_gb = GrowthBook {
+            context: Context {
+                attributes: nested.clone(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let flat_gb = GrowthBook {
+            context: Context {
+                attributes: flat,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let nested_result = nested_gb.run(&experiment);
+        let flat_result = flat_gb.run(&Experiment {
+            hash_attribute: Some("orgId".to_string()),
+            ..experiment
+        });
+        assert!(nested_result.in_experiment);
+        assert_eq!(nested_result.hash_attribute, "org.id");
+        assert_eq!(nested_result.hash_value, json!("org-42"));
+        assert_eq!(nested_result.bucket, flat_result.bucket);
+        assert_eq!(nested_result.variation_id, flat_result.variation_id);
+
+        let gb = GrowthBook {
+            context: Context {
+                attributes: nested,
+                features: Arc::new(
+                    serde_json::from_value(json!({
+                        "rollout": {
+                            "defaultValue": false,
+                            "rules": [{"force": true, "coverage": 1.0, "hashAttribute": "org.id"}]
+                        },
+                        "filtered": {
+                            "defaultValue": false,
+                            "rules": [{"force": true, "filters": [{"seed": "seed", "ranges": [[0.5, 1.0]], "attribute": "org.id"}]}]
+                        }
+                    }))
+                    .unwrap(),
+                ),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(gb.eval_feature("rollout").value, json!(true));
+        assert_eq!(gb.eval_feature("filtered").value, json!(true));
+    }
 }
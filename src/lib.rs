@@ -1,8 +1,19 @@
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod cache;
 pub mod condition;
 pub mod growthbook;
 pub mod model;
+#[cfg(feature = "proxy")]
+pub mod proxy;
+pub mod registry;
 pub mod repository;
+pub mod sse;
+pub mod sticky_bucket;
+pub mod transport;
 pub mod util;
+#[cfg(feature = "webhook")]
+pub mod webhook;
 
 pub fn add(left: usize, right: usize) -> usize {
     left + right
@@ -16,7 +27,7 @@ mod json_tests {
     use serde_json::{from_str, Map, Value};
 
     use crate::growthbook::GrowthBook;
-    use crate::model::{BucketRange, Context, Experiment, ExperimentResult, FeatureResult, Namespace};
+    use crate::model::{BucketRange, Context, Experiment, ExperimentResult, FeatureResult, Namespace, SavedGroups};
     use crate::{condition, util};
 
     fn get_test_case_blob(key: &str) -> Option<Value> {
@@ -190,7 +201,11 @@ mod json_tests {
             let condition: &Value = &tc[1];
             let attributes: &Value = &tc[2];
             let expected: bool = tc[3].as_bool().unwrap();
-            let actual = condition::eval_condition(attributes, condition);
+            let saved_groups: SavedGroups = tc
+                .get(4)
+                .map(|groups| serde_json::from_value(groups.clone()).unwrap())
+                .unwrap_or_default();
+            let actual = condition::eval_condition(attributes, condition, &saved_groups);
             assert_eq!(actual, expected, "eval_condition test case '{}' failed", case_name);
         }
     }
@@ -215,7 +230,7 @@ mod json_tests {
                 let attribute: &str = tc[0].as_str().unwrap();
                 let condition: &str = tc[1].as_str().unwrap();
                 let expected: bool = tc[2].as_bool().unwrap();
-                let actual = condition::eval_operator_condition(op, Some(&Value::from(attribute)), &Value::from(condition));
+                let actual = condition::eval_operator_condition(op, Some(&Value::from(attribute)), &Value::from(condition), &SavedGroups::default());
                 assert_eq!(actual, expected, "version_compare test case '{}', index '{}' failed", op, i);
             }
         }
@@ -1,14 +1,20 @@
 use std::collections::HashMap;
 use std::fmt;
 use std::fmt::Debug;
+use std::sync::Arc;
 
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value;
 
+use crate::sticky_bucket::StickyBucketService;
+use crate::util::CompiledUrlTarget;
+
 pub type Attributes = Value;
 pub type Condition = Value;
 pub type FeatureMap = HashMap<String, Feature>;
 pub type ForcedVariationsMap = HashMap<String, i32>;
+// group id to the attribute values in that group, referenced by $inGroup / $notInGroup
+pub type SavedGroups = HashMap<String, Vec<Value>>;
 
 pub type TrackingCallbackFn = dyn Fn(&Experiment, &ExperimentResult) + Send + Sync;
 pub struct TrackingCallback(pub Box<TrackingCallbackFn>);
@@ -132,12 +138,80 @@ pub struct Experiment {
     pub namespace: Option<Namespace>,
     pub force: Option<i32>,
     pub hash_attribute: Option<String>,
+    // hashed on instead when hash_attribute is missing, e.g. a device id for anonymous users
+    pub fallback_attribute: Option<String>,
     pub hash_version: Option<i32>,
     pub meta: Vec<VariationMeta>,
     pub filters: Vec<Filter>,
     pub seed: Option<String>,
     pub name: Option<String>,
     pub phase: Option<String>,
+    pub parent_conditions: Vec<ParentCondition>,
+    // sticky bucketing: assignments are stored per bucket_version, users holding one older than
+    // min_bucket_version are excluded, as are users assigned to a blocked variation
+    pub bucket_version: Option<i32>,
+    pub min_bucket_version: Option<i32>,
+    pub blocked_variations: Vec<i32>,
+    pub disable_sticky_bucketing: Option<bool>,
+    // the rest only comes with the payload's experiments list (url redirect and visual experiments)
+    pub change_id: Option<String>,
+    pub url_patterns: Vec<UrlTarget>,
+    pub manual: Option<bool>,
+    pub persist_query_string: Option<bool>,
+}
+
+impl Experiment {
+    // what each variation changes, variations that are not change objects come back empty
+    pub fn changes(&self) -> Vec<VariationChanges> {
+        self.variations
+            .iter()
+            .map(|variation| serde_json::from_value(variation.clone()).unwrap_or_default())
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UrlTargetType {
+    #[default]
+    Simple,
+    Regex,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, rename_all = "camelCase")]
+pub struct UrlTarget {
+    #[serde(default = "url_target_include")]
+    pub include: bool,
+    #[serde(rename = "type")]
+    pub target_type: UrlTargetType,
+    pub pattern: String,
+    #[serde(skip)]
+    pub compiled: CompiledUrlTarget,
+}
+
+impl Default for UrlTarget {
+    fn default() -> Self {
+        UrlTarget {
+            include: url_target_include(),
+            target_type: Default::default(),
+            pattern: Default::default(),
+            compiled: Default::default(),
+        }
+    }
+}
+
+const fn url_target_include() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, rename_all = "camelCase")]
+pub struct VariationChanges {
+    pub css: Option<String>,
+    pub js: Option<String>,
+    pub dom_mutations: Vec<Value>,
+    pub url_redirect: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
@@ -154,6 +228,8 @@ pub struct ExperimentResult {
     pub bucket: f32,
     pub name: Option<String>,
     pub passthrough: bool,
+    // the variation came from a stored sticky bucket instead of the hash
+    pub sticky_bucket_used: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
@@ -174,6 +250,7 @@ pub struct FeatureRule {
     pub weights: Vec<f32>,
     pub namespace: Option<Namespace>,
     pub hash_attribute: Option<String>,
+    pub fallback_attribute: Option<String>,
     pub hash_version: Option<i32>,
     pub range: Option<BucketRange>,
     pub ranges: Vec<BucketRange>,
@@ -183,6 +260,21 @@ pub struct FeatureRule {
     pub name: Option<String>,
     pub phase: Option<String>,
     pub tracks: Vec<TrackData>,
+    pub parent_conditions: Vec<ParentCondition>,
+    pub bucket_version: Option<i32>,
+    pub min_bucket_version: Option<i32>,
+    pub blocked_variations: Vec<i32>,
+    pub disable_sticky_bucketing: Option<bool>,
+}
+
+// a condition on another feature's evaluated value, which is matched as `{"value": ...}`.
+// a failed gate turns the whole feature off, any other failed parent condition only skips the rule
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ParentCondition {
+    pub id: String,
+    pub condition: Condition,
+    pub gate: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
@@ -196,6 +288,10 @@ pub enum Source {
     Force,
     #[serde(rename = "experiment")]
     Experiment,
+    #[serde(rename = "prerequisite")]
+    Prerequisite,
+    #[serde(rename = "cyclicPrerequisite")]
+    CyclicPrerequisite,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
@@ -223,9 +319,15 @@ pub struct Context {
     pub enabled: bool,
     pub attributes: Attributes,
     pub url: String,
-    pub features: FeatureMap,
+    // shared with the repository snapshot, so building a context per request does not copy the features
+    pub features: Arc<FeatureMap>,
     pub forced_variations: ForcedVariationsMap,
     pub qa_mode: bool,
+    pub saved_groups: SavedGroups,
+    pub experiments: Vec<Experiment>,
+    // keeps users in their variation once bucketed, see sticky_bucket
+    #[serde(skip)]
+    pub sticky_bucket_service: Option<Arc<dyn StickyBucketService>>,
 }
 
 impl Default for Context {
@@ -237,6 +339,9 @@ impl Default for Context {
             features: Default::default(),
             forced_variations: Default::default(),
             qa_mode: Default::default(),
+            saved_groups: Default::default(),
+            experiments: Default::default(),
+            sticky_bucket_service: None,
         }
     }
 }
@@ -376,12 +481,22 @@ mod tests {
             namespace: None,
             force: None,
             hash_attribute: None,
+            fallback_attribute: None,
             hash_version: None,
             meta: vec![],
             filters: vec![],
             seed: None,
             name: None,
             phase: None,
+            parent_conditions: vec![],
+            bucket_version: None,
+            min_bucket_version: None,
+            blocked_variations: vec![],
+            disable_sticky_bucketing: None,
+            change_id: None,
+            url_patterns: vec![],
+            manual: None,
+            persist_query_string: None,
         };
         assert_eq!(experiment.key, "".to_string());
         assert_eq!(experiment.variations, Vec::<Value>::new());
@@ -399,6 +514,8 @@ mod tests {
         assert_eq!(experiment.seed, None);
         assert_eq!(experiment.name, None);
         assert_eq!(experiment.phase, None);
+        assert_eq!(experiment.parent_conditions, vec![]);
+        assert_eq!(experiment.url_patterns, vec![]);
 
         let experiment = Experiment {
             key: "something".to_string(),
@@ -470,6 +587,7 @@ mod tests {
             bucket: 0.0,
             name: None,
             passthrough: true,
+            sticky_bucket_used: false,
         };
         assert!(experiment_result.in_experiment);
         assert_eq!(experiment_result.variation_id, 0);
@@ -482,6 +600,7 @@ mod tests {
         assert_eq!(experiment_result.bucket, 0.0);
         assert_eq!(experiment_result.name, None);
         assert!(experiment_result.passthrough);
+        assert!(!experiment_result.sticky_bucket_used);
 
         let experiment_result = ExperimentResult { ..Default::default() };
         assert!(!experiment_result.in_experiment);
@@ -536,6 +655,7 @@ mod tests {
                 namespace: None,
                 force: None,
                 hash_attribute: None,
+                fallback_attribute: None,
                 hash_version: None,
                 meta: vec![],
                 filters: vec![Filter {
@@ -550,6 +670,15 @@ mod tests {
                 seed: None,
                 name: None,
                 phase: None,
+                parent_conditions: vec![],
+                bucket_version: None,
+                min_bucket_version: None,
+                blocked_variations: vec![],
+                disable_sticky_bucketing: None,
+                change_id: None,
+                url_patterns: vec![],
+                manual: None,
+                persist_query_string: None,
             }
         );
 
@@ -567,6 +696,7 @@ mod tests {
                 bucket: 0.0,
                 name: None,
                 passthrough: true,
+                sticky_bucket_used: false,
             }
         );
     }
@@ -623,7 +753,7 @@ mod tests {
             ..Default::default()
         };
         assert_eq!(context.attributes, Value::Null);
-        assert_eq!(context.features, FeatureMap::default());
+        assert_eq!(*context.features, FeatureMap::default());
         assert_eq!(context.forced_variations, ForcedVariationsMap::default());
         assert!(!context.qa_mode);
         assert!(context.enabled);
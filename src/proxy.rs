@@ -0,0 +1,283 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use axum::extract::{Path, State};
+use axum::http::header::{CONTENT_TYPE, ETAG, IF_NONE_MATCH};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use log::error;
+use serde_json::Value;
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::StreamExt;
+
+use crate::registry::{RepositoryKey, RepositoryRegistry};
+use crate::repository::{FeatureRefreshCallback, FeatureRepository, FeatureRepositoryError, RefreshHandle, RefreshOutcome};
+
+#[derive(Debug, Clone, PartialEq)]
+struct ServedPayload {
+    body: String,
+    etag: String,
+}
+
+impl ServedPayload {
+    fn new(payload: &Value) -> Self {
+        let body = payload.to_string();
+        let mut hasher = DefaultHasher::new();
+        body.hash(&mut hasher);
+        ServedPayload {
+            etag: format!("\"{:016x}\"", hasher.finish()),
+            body,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ProxyEntry {
+    repository: FeatureRepository,
+    served: RwLock<Option<ServedPayload>>,
+    updates: broadcast::Sender<String>,
+}
+
+impl ProxyEntry {
+    fn served(&self) -> Option<ServedPayload> {
+        if let Ok(served) = self.served.read() {
+            if served.is_some() {
+                return served.clone();
+            }
+        }
+        // a payload without features never fires the refresh callbacks, so pick it up here
+        let payload = self.repository.payload.read().ok()?.clone()?;
+        Some(self.update(&payload))
+    }
+
+    fn update(&self, payload: &Value) -> ServedPayload {
+        let served = ServedPayload::new(payload);
+        match self.served.write() {
+            Ok(mut current) => *current = Some(served.clone()),
+            Err(_) => error!("Error writing proxied payload"),
+        }
+        served
+    }
+}
+
+// re-serves the payloads of the configured client keys, so downstream sdks can point their api_host here.
+// /api/features/{clientKey} answers with ETags, /sub/{clientKey} streams every change as a `features` event.
+// payloads are passed on as received, encrypted ones stay encrypted
+#[derive(Debug, Clone)]
+pub struct FeatureProxy {
+    pub registry: RepositoryRegistry,
+    entries: Arc<HashMap<String, Arc<ProxyEntry>>>,
+}
+
+impl FeatureProxy {
+    // client keys come with their decryption key, the proxy needs it to tell a good payload from a broken one
+    pub fn new(registry: RepositoryRegistry, api_host: &str, client_keys: &[(String, Option<String>)]) -> Self {
+        let mut entries = HashMap::new();
+        for (client_key, decryption_key) in client_keys {
+            let mut repository = registry.register(api_host, client_key, decryption_key.clone());
+            let (updates, _) = broadcast::channel(16);
+            let entry = Arc::new(ProxyEntry {
+                repository: repository.clone(),
+                served: RwLock::new(None),
+                updates,
+            });
+
+            let callback_entry = Arc::downgrade(&entry);
+            let payload = repository.payload.clone();
+            repository.add_refresh_callback(FeatureRefreshCallback(Box::new(move |_features| {
+                let (entry, payload) = match (callback_entry.upgrade(), payload.read()) {
+                    (Some(entry), Ok(payload)) => (entry, payload),
+                    _ => return,
+                };
+                if let Some(payload) = payload.as_ref() {
+                    let served = entry.update(payload);
+                    // nobody listening is fine
+                    let _ = entry.updates.send(served.body);
+                }
+            })));
+            entries.insert(client_key.clone(), entry);
+        }
+        FeatureProxy {
+            registry,
+            entries: Arc::new(entries),
+        }
+    }
+
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route("/api/features/:client_key", get(serve_features))
+            .route("/sub/:client_key", get(stream_features))
+            .with_state(self.clone())
+    }
+
+    pub async fn refresh_all(&self) -> Vec<(RepositoryKey, Result<RefreshOutcome, FeatureRepositoryError>)> {
+        self.registry.refresh_all().await
+    }
+
+    pub fn start_polling(&self, interval: Duration) -> RefreshHandle {
+        self.registry.start_polling(interval)
+    }
+
+    fn entry(&self, client_key: &str) -> Option<Arc<ProxyEntry>> {
+        self.entries.get(client_key).cloned()
+    }
+}
+
+async fn serve_features(State(proxy): State<FeatureProxy>, Path(client_key): Path<String>, headers: HeaderMap) -> Response {
+    let entry = match proxy.entry(&client_key) {
+        Some(entry) => entry,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let served = match entry.served() {
+        Some(served) => served,
+        // not fetched from upstream yet
+        None => return StatusCode::SERVICE_UNAVAILABLE.into_response(),
+    };
+
+    let mut response_headers = HeaderMap::new();
+    if let Ok(etag) = HeaderValue::from_str(&served.etag) {
+        response_headers.insert(ETAG, etag);
+    }
+    let not_modified = headers
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|etag| etag.trim() == served.etag));
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, response_headers).into_response();
+    }
+    response_headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    (StatusCode::OK, response_headers, served.body).into_response()
+}
+
+async fn stream_features(State(proxy): State<FeatureProxy>, Path(client_key): Path<String>) -> Response {
+    let entry = match proxy.entry(&client_key) {
+        Some(entry) => entry,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+    // subscribe before reading the current payload so no update falls in between
+    let updates = BroadcastStream::new(entry.updates.subscribe()).filter_map(Result::ok);
+    let current = entry.served().map(|served| served.body);
+    let events = tokio_stream::iter(current)
+        .chain(updates)
+        .map(|body| Ok::<_, Infallible>(Event::default().event("features").data(body)));
+    Sse::new(events).keep_alive(KeepAlive::default()).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    use tokio::time::sleep;
+
+    use crate::proxy::FeatureProxy;
+    use crate::registry::RepositoryRegistry;
+    use crate::repository::FeatureRepository;
+
+    const FEATURES: &str = r#"{"features": {"greeting": {"defaultValue": "hello"}}, "dateUpdated": "2023-08-02T19:11:46.550Z"}"#;
+    const UPDATED_FEATURES: &str = r#"{"features": {"greeting": {"defaultValue": "hi"}}, "dateUpdated": "2023-08-03T10:00:00.000Z"}"#;
+
+    async fn spawn_proxy(proxy: &FeatureProxy) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address: SocketAddr = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener).unwrap().serve(proxy.router().into_make_service());
+        tokio::spawn(server);
+        format!("http://{}", address)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_serves_features_with_etag() {
+        let mut upstream = mockito::Server::new_async().await;
+        upstream
+            .mock("GET", "/api/features/sdk-proxied")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(FEATURES)
+            .create();
+
+        let proxy = FeatureProxy::new(RepositoryRegistry::default(), &upstream.url(), &[("sdk-proxied".to_string(), None)]);
+        let proxy_url = spawn_proxy(&proxy).await;
+        let client = reqwest::Client::new();
+
+        let res = client.get(format!("{}/api/features/sdk-proxied", proxy_url)).send().await.unwrap();
+        assert_eq!(res.status(), 503);
+
+        proxy.refresh_all().await;
+        let res = client.get(format!("{}/api/features/sdk-proxied", proxy_url)).send().await.unwrap();
+        assert_eq!(res.status(), 200);
+        let etag = res.headers()["etag"].to_str().unwrap().to_string();
+        let body: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+        assert_eq!(body, serde_json::from_str::<serde_json::Value>(FEATURES).unwrap());
+
+        let res = client
+            .get(format!("{}/api/features/sdk-proxied", proxy_url))
+            .header("if-none-match", &etag)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 304);
+
+        let res = client.get(format!("{}/api/features/sdk-unknown", proxy_url)).send().await.unwrap();
+        assert_eq!(res.status(), 404);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_downstream_sdk_streams_updates() {
+        let mut upstream = mockito::Server::new_async().await;
+        upstream
+            .mock("GET", "/api/features/sdk-proxied")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(FEATURES)
+            .expect(1)
+            .create();
+        upstream
+            .mock("GET", "/api/features/sdk-proxied")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(UPDATED_FEATURES)
+            .create();
+
+        let proxy = FeatureProxy::new(RepositoryRegistry::default(), &upstream.url(), &[("sdk-proxied".to_string(), None)]);
+        proxy.refresh_all().await;
+        let proxy_url = spawn_proxy(&proxy).await;
+
+        let downstream = FeatureRepository {
+            api_host: proxy_url,
+            client_key: Some("sdk-proxied".to_string()),
+            ..Default::default()
+        };
+        let _handle = downstream.start_streaming();
+        let greeting = || {
+            downstream
+                .features
+                .read()
+                .unwrap()
+                .get("greeting")
+                .and_then(|feature| feature.default_value.clone())
+        };
+
+        let mut timeout = 2000;
+        while greeting().is_none() && timeout > 0 {
+            sleep(Duration::from_millis(10)).await;
+            timeout -= 10;
+        }
+        assert_eq!(greeting(), Some(serde_json::json!("hello")));
+
+        proxy.refresh_all().await;
+        let mut timeout = 2000;
+        while greeting() != Some(serde_json::json!("hi")) && timeout > 0 {
+            sleep(Duration::from_millis(10)).await;
+            timeout -= 10;
+        }
+        assert_eq!(greeting(), Some(serde_json::json!("hi")));
+    }
+}
@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+#[cfg(feature = "tokio")]
+use std::time::Duration;
+
+use log::error;
+
+use crate::cache::{FeatureCache, NamespacedFeatureCache};
+use crate::model::FeatureMap;
+#[cfg(feature = "tokio")]
+use crate::repository::RefreshHandle;
+use crate::repository::{FeatureRepository, FeatureRepositoryError, RefreshOutcome};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RepositoryKey {
+    pub api_host: String,
+    pub client_key: String,
+}
+
+impl RepositoryKey {
+    pub fn new(api_host: &str, client_key: &str) -> Self {
+        RepositoryKey {
+            api_host: api_host.to_string(),
+            client_key: client_key.to_string(),
+        }
+    }
+}
+
+// holds one FeatureRepository per sdk connection, created on first use.
+// every repository takes its settings from `template` and shares its transport, and with it the HTTP client
+#[derive(Debug, Clone)]
+pub struct RepositoryRegistry {
+    pub template: FeatureRepository,
+    repositories: Arc<RwLock<HashMap<RepositoryKey, FeatureRepository>>>,
+}
+
+impl Default for RepositoryRegistry {
+    fn default() -> Self {
+        RepositoryRegistry::new(FeatureRepository::default())
+    }
+}
+
+impl RepositoryRegistry {
+    pub fn new(template: FeatureRepository) -> Self {
+        RepositoryRegistry {
+            template,
+            repositories: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    // adds a connection up front, needed for encrypted payloads since the decryption key differs per client key.
+    // an already registered repository keeps its state and only gets the new decryption key, clones included
+    pub fn register(&self, api_host: &str, client_key: &str, decryption_key: Option<String>) -> FeatureRepository {
+        let key = RepositoryKey::new(api_host, client_key);
+        match self.repositories.write() {
+            Ok(mut repositories) => {
+                let repository = repositories.entry(key).or_insert_with(|| self.create(api_host, client_key));
+                repository.set_decryption_key(decryption_key);
+                repository.clone()
+            }
+            Err(_) => {
+                error!("Error registering repository");
+                let repository = self.create(api_host, client_key);
+                repository.set_decryption_key(decryption_key);
+                repository
+            }
+        }
+    }
+
+    // clones share their state with the registered repository
+    pub fn repository(&self, api_host: &str, client_key: &str) -> FeatureRepository {
+        let key = RepositoryKey::new(api_host, client_key);
+        match self.repositories.read() {
+            Ok(repositories) => {
+                if let Some(repository) = repositories.get(&key) {
+                    return repository.clone();
+                }
+            }
+            Err(_) => error!("Error reading repositories"),
+        }
+        match self.repositories.write() {
+            Ok(mut repositories) => repositories.entry(key).or_insert_with(|| self.create(api_host, client_key)).clone(),
+            Err(_) => {
+                error!("Error adding repository");
+                self.create(api_host, client_key)
+            }
+        }
+    }
+
+    pub async fn get_features(&self, api_host: &str, client_key: &str) -> Arc<FeatureMap> {
+        self.repository(api_host, client_key).get_features().await
+    }
+
+    pub fn keys(&self) -> Vec<RepositoryKey> {
+        match self.repositories.read() {
+            Ok(repositories) => repositories.keys().cloned().collect(),
+            Err(_) => {
+                error!("Error reading repositories");
+                vec![]
+            }
+        }
+    }
+
+    pub fn remove(&self, api_host: &str, client_key: &str) -> Option<FeatureRepository> {
+        match self.repositories.write() {
+            Ok(mut repositories) => repositories.remove(&RepositoryKey::new(api_host, client_key)),
+            Err(_) => {
+                error!("Error removing repository");
+                None
+            }
+        }
+    }
+
+    pub async fn refresh_all(&self) -> Vec<(RepositoryKey, Result<RefreshOutcome, FeatureRepositoryError>)> {
+        let mut results = vec![];
+        for key in self.keys() {
+            let mut repository = self.repository(&key.api_host, &key.client_key);
+            let result = repository.refresh().await;
+            results.push((key, result));
+        }
+        results
+    }
+
+    // refreshes every registered repository on a schedule, including ones added after polling started
+    #[cfg(feature = "tokio")]
+    pub fn start_polling(&self, interval: Duration) -> RefreshHandle {
+        let registry = self.clone();
+        RefreshHandle::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                registry.refresh_all().await;
+            }
+        })
+    }
+
+    // settings are copied from the template, while features, timestamps and callbacks start out fresh
+    fn create(&self, api_host: &str, client_key: &str) -> FeatureRepository {
+        let template = &self.template;
+        FeatureRepository {
+            api_host: api_host.to_string(),
+            client_key: Some(client_key.to_string()),
+            decryption_key: Arc::new(RwLock::new(None)),
+            ttl_seconds: template.ttl_seconds,
+            timeout: template.timeout,
+            retry_policy: template.retry_policy.clone(),
+            headers: template.headers.clone(),
+            transport: template.transport.clone(),
+            // cache entries are keyed by client key, which two api hosts may have in common
+            cache: template
+                .cache
+                .clone()
+                .map(|cache| Arc::new(NamespacedFeatureCache::new(api_host, cache)) as Arc<dyn FeatureCache>),
+            cache_max_stale_seconds: template.cache_max_stale_seconds,
+            remote_eval_cache_size: template.remote_eval_cache_size,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(all(test, feature = "reqwest"))]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::cache::InMemoryFeatureCache;
+    use crate::registry::{RepositoryKey, RepositoryRegistry};
+    use crate::repository::{FeatureRepository, RefreshOutcome};
+
+    #[tokio::test]
+    async fn test_repositories_are_created_once_per_key() {
+        let mut mock_server = mockito::Server::new_async().await;
+        let first = mock_server
+            .mock("GET", "/api/features/sdk-first")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"features": {"greeting": {"defaultValue": "hello"}}}"#)
+            .expect(1)
+            .create();
+        let second = mock_server
+            .mock("GET", "/api/features/sdk-second")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"features": {"greeting": {"defaultValue": "hi"}, "dark_mode": {"defaultValue": true}}}"#)
+            .expect(1)
+            .create();
+
+        let registry = RepositoryRegistry::new(FeatureRepository {
+            ttl_seconds: 600,
+            ..Default::default()
+        });
+        let host = mock_server.url();
+        assert_eq!(registry.repository(&host, "sdk-first").refresh().await, Ok(RefreshOutcome::Updated));
+        assert_eq!(registry.repository(&host, "sdk-second").refresh().await, Ok(RefreshOutcome::Updated));
+
+        assert_eq!(registry.get_features(&host, "sdk-first").await.len(), 1);
+        assert_eq!(registry.get_features(&host, "sdk-second").await.len(), 2);
+        first.assert_async().await;
+        second.assert_async().await;
+
+        let mut keys = registry.keys();
+        keys.sort_by(|a, b| a.client_key.cmp(&b.client_key));
+        assert_eq!(
+            keys,
+            vec![RepositoryKey::new(&host, "sdk-first"), RepositoryKey::new(&host, "sdk-second")]
+        );
+        assert_eq!(registry.repository(&host, "sdk-first").ttl_seconds, 600);
+    }
+
+    #[test]
+    fn test_repositories_share_transport() {
+        let registry = RepositoryRegistry::default();
+        let first = registry.repository("https://cdn.growthbook.io", "sdk-first");
+        let second = registry.repository("https://growthbook.example.com", "sdk-first");
+        assert!(Arc::ptr_eq(
+            first.transport.as_ref().unwrap(),
+            registry.template.transport.as_ref().unwrap()
+        ));
+        assert!(Arc::ptr_eq(first.transport.as_ref().unwrap(), second.transport.as_ref().unwrap()));
+        assert!(!Arc::ptr_eq(&first.features, &second.features));
+        assert_eq!(registry.keys().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_register_sets_decryption_key() {
+        let mut mock_server = mockito::Server::new_async().await;
+        mock_server
+            .mock("GET", "/api/features/sdk-encrypted")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"features": {}, "encryptedFeatures": "UqANSnJ7xTTK9y2PALtnwQ==.BZAstXrI9eh9qlvp7VinD8CKk9ZE8755vnFtkClJNYstTUwF4FKwWWq84F/DFTe+2Xlzbys83S1Ih6XIFhoigKIQeImlnzR3GJ6Bvj3REbKccw9TJz4bX3ozFzSNBbZbLAynnd9aTLK0PAYASLXKtIaAs/K0WSbV7mM95CVMt9DU7w1TKme/tQcqfEn+CJhi2WHNdEzGs18j9t7zXcRgdAvXizLzP7HdOnCmfXy9bZbpqWmAdUBZ0yhmb2PGXa5FBwet7h1MV0kRFX++WocwjA=="}"#)
+            .create();
+
+        let registry = RepositoryRegistry::default();
+        let host = mock_server.url();
+        let mut handed_out_before = registry.repository(&host, "sdk-encrypted");
+        registry.register(&host, "sdk-encrypted", Some("BhB1wORFmZLTDjbvstvS8w==".to_string()));
+        let results = registry.refresh_all().await;
+        assert_eq!(results, vec![(RepositoryKey::new(&host, "sdk-encrypted"), Ok(RefreshOutcome::Updated))]);
+        assert_eq!(registry.repository(&host, "sdk-encrypted").features.read().unwrap().len(), 1);
+        assert_eq!(handed_out_before.refresh().await, Ok(RefreshOutcome::Unchanged));
+    }
+
+    #[tokio::test]
+    async fn test_hosts_sharing_a_client_key_keep_their_cache_entries_apart() {
+        let mut first_server = mockito::Server::new_async().await;
+        let first = first_server
+            .mock("GET", "/api/features/sdk-shared")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"features": {"greeting": {"defaultValue": "hello"}}}"#)
+            .expect(1)
+            .create();
+        let mut second_server = mockito::Server::new_async().await;
+        let second = second_server
+            .mock("GET", "/api/features/sdk-shared")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"features": {"greeting": {"defaultValue": "hi"}, "dark_mode": {"defaultValue": true}}}"#)
+            .expect(1)
+            .create();
+
+        let template = FeatureRepository {
+            ttl_seconds: 600,
+            cache: Some(Arc::new(InMemoryFeatureCache::default())),
+            ..Default::default()
+        };
+        let (first_host, second_host) = (first_server.url(), second_server.url());
+        let registry = RepositoryRegistry::new(template.clone());
+        assert_eq!(
+            registry.repository(&first_host, "sdk-shared").refresh().await,
+            Ok(RefreshOutcome::Updated)
+        );
+        assert_eq!(
+            registry.repository(&second_host, "sdk-shared").refresh().await,
+            Ok(RefreshOutcome::Updated)
+        );
+
+        // a restarted registry starts out from the cache, without fetching again
+        let restarted = RepositoryRegistry::new(template);
+        assert_eq!(restarted.get_features(&first_host, "sdk-shared").await.len(), 1);
+        assert_eq!(restarted.get_features(&second_host, "sdk-shared").await.len(), 2);
+        first.assert_async().await;
+        second.assert_async().await;
+    }
+}
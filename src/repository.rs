@@ -1,14 +1,28 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
 use std::fmt;
-use std::fmt::Debug;
+use std::fmt::{Debug, Display};
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 use log::{error, warn};
-use reqwest::header::USER_AGENT;
-use reqwest::{Client, ClientBuilder};
+#[cfg(all(feature = "tokio", feature = "reqwest"))]
+use reqwest::header::{ACCEPT, USER_AGENT};
+use serde::de::DeserializeOwned;
 use serde_json::{json, Value};
 
+use crate::cache::{CacheError, CachedPayload, FeatureCache};
 use crate::growthbook::SDK_VERSION;
-use crate::model::FeatureMap;
+use crate::model::{Context, Experiment, FeatureMap, SavedGroups};
+#[cfg(all(feature = "tokio", feature = "reqwest"))]
+use crate::sse::{SseEvent, SseParser};
+#[cfg(feature = "reqwest")]
+use crate::transport::ReqwestTransport;
+use crate::transport::{FeatureRequest, FeatureTransport, TransportError};
 use crate::util;
 
 pub struct FeatureRefreshCallback(pub Box<dyn Fn(&FeatureMap) + Send + Sync>);
@@ -19,16 +33,156 @@ impl Debug for FeatureRefreshCallback {
     }
 }
 
+pub type FeatureDiffCallbackFn = dyn Fn(&FeatureMap, &FeatureDiff) + Send + Sync;
+pub struct FeatureDiffCallback(pub Box<FeatureDiffCallbackFn>);
+
+impl Debug for FeatureDiffCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<callback_function>")
+    }
+}
+
+// feature keys that changed in a refresh, each list is sorted
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeatureDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<String>,
+}
+
+impl FeatureDiff {
+    pub fn between(old: &FeatureMap, new: &FeatureMap) -> Self {
+        let mut diff = FeatureDiff::default();
+        for (key, feature) in new.iter() {
+            match old.get(key) {
+                None => diff.added.push(key.clone()),
+                Some(old_feature) if old_feature != feature => diff.modified.push(key.clone()),
+                Some(_) => {}
+            }
+        }
+        diff.removed = old.keys().filter(|key| !new.contains_key(*key)).cloned().collect();
+        diff.added.sort();
+        diff.removed.sort();
+        diff.modified.sort();
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+pub struct FeatureRepositoryErrorCallback(pub Box<dyn Fn(&FeatureRepositoryError) + Send + Sync>);
+
+impl Debug for FeatureRepositoryErrorCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<callback_function>")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FeatureRepositoryError {
+    MissingClientKey,
+    MissingTransport,
+    MissingDecryptionKey,
+    Transport(TransportError),
+    // the server answered with a status other than 2xx or 304
+    Status(u16),
+    InvalidPayload(String),
+    Decryption,
+    Cache(CacheError),
+    LockPoisoned,
+}
+
+impl Display for FeatureRepositoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeatureRepositoryError::MissingClientKey => write!(f, "client key not set"),
+            FeatureRepositoryError::MissingTransport => write!(f, "HTTP transport not set"),
+            FeatureRepositoryError::MissingDecryptionKey => write!(f, "decryption key not set, but found encrypted features"),
+            FeatureRepositoryError::Transport(e) => write!(f, "request failed: {}", e),
+            FeatureRepositoryError::Status(status) => write!(f, "unexpected status {}", status),
+            FeatureRepositoryError::InvalidPayload(message) => write!(f, "invalid payload: {}", message),
+            FeatureRepositoryError::Decryption => write!(f, "unable to decrypt features"),
+            FeatureRepositoryError::Cache(e) => write!(f, "feature cache failed: {}", e),
+            FeatureRepositoryError::LockPoisoned => write!(f, "features lock poisoned"),
+        }
+    }
+}
+
+impl Error for FeatureRepositoryError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefreshOutcome {
+    Updated,
+    // a payload was fetched but held the same features
+    Unchanged,
+    NotModified,
+    // another refresh was already in flight, this one did nothing
+    InProgress,
+    // the payload's dateUpdated is older than the features held, so it was dropped
+    Outdated,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadSource {
+    Network,
+    Cache,
+    Bootstrap,
+}
+
+// health of the repository for readiness probes, timestamps are unix seconds
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RepositoryStatus {
+    pub last_success_at: Option<i64>,
+    pub last_attempt_at: Option<i64>,
+    // after failed refreshes get_features() waits until then before fetching again, refresh() doesn't
+    pub next_attempt_at: Option<i64>,
+    pub last_error: Option<FeatureRepositoryError>,
+    pub consecutive_failures: u32,
+    // dateUpdated of the payload the features came from
+    pub date_updated: Option<String>,
+    pub feature_count: usize,
+    pub source: Option<PayloadSource>,
+}
+
+// attribute set to when it was evaluated and the features it evaluated to
+pub type RemoteEvalCache = HashMap<String, (i64, Arc<FeatureMap>)>;
+
 #[derive(Debug, Clone)]
 pub struct FeatureRepository {
     pub api_host: String,
     pub client_key: Option<String>,
-    pub decryption_key: Option<String>,
+    // shared by every clone, so set_decryption_key reaches repositories already handed out
+    pub decryption_key: Arc<RwLock<Option<String>>>,
     pub ttl_seconds: i64,
     pub timeout: u64,
     pub refreshed_at: Arc<RwLock<i64>>,
     pub refresh_callbacks: Arc<RwLock<Vec<FeatureRefreshCallback>>>,
-    pub features: Arc<RwLock<FeatureMap>>,
+    pub diff_callbacks: Arc<RwLock<Vec<FeatureDiffCallback>>>,
+    pub error_callbacks: Arc<RwLock<Vec<FeatureRepositoryErrorCallback>>>,
+    // an immutable snapshot swapped whole on refresh, readers only clone the Arc
+    pub features: Arc<RwLock<Arc<FeatureMap>>>,
+    pub saved_groups: Arc<RwLock<SavedGroups>>,
+    // url redirect and visual experiments, pass them on in Context.experiments
+    pub experiments: Arc<RwLock<Vec<Experiment>>>,
+    // the payload the features were decoded from, exactly as received
+    pub payload: Arc<RwLock<Option<Value>>>,
+    pub etag: Arc<RwLock<Option<String>>>,
+    pub last_modified: Arc<RwLock<Option<String>>>,
+    pub retry_policy: RetryPolicy,
+    // sent with every request, e.g. an Authorization header for a self-hosted GrowthBook behind a gateway
+    pub headers: Vec<(String, String)>,
+    // proxy and tls settings live with the transport, see ReqwestTransport::with_options
+    pub transport: Option<Arc<dyn FeatureTransport>>,
+    pub cache: Option<Arc<dyn FeatureCache>>,
+    pub cache_max_stale_seconds: i64,
+    pub cache_saved_at: Arc<RwLock<i64>>,
+    pub refreshing: Arc<AtomicBool>,
+    pub refresh_status: Arc<RwLock<RepositoryStatus>>,
+    // pre-evaluated features per attribute set, see remote_eval
+    pub remote_eval_cache: Arc<RwLock<RemoteEvalCache>>,
+    pub remote_eval_cache_size: usize,
 }
 
 impl Default for FeatureRepository {
@@ -36,18 +190,54 @@ impl Default for FeatureRepository {
         FeatureRepository {
             api_host: "https://cdn.growthbook.io".to_string(),
             client_key: None,
-            decryption_key: None,
+            decryption_key: Arc::new(RwLock::new(None)),
             ttl_seconds: 60,
             timeout: 10,
             refreshed_at: Arc::new(RwLock::new(0)),
             refresh_callbacks: Arc::new(RwLock::new(vec![])),
-            features: Arc::new(RwLock::new(FeatureMap::default())),
+            diff_callbacks: Arc::new(RwLock::new(vec![])),
+            error_callbacks: Arc::new(RwLock::new(vec![])),
+            features: Arc::new(RwLock::new(Arc::new(FeatureMap::default()))),
+            saved_groups: Arc::new(RwLock::new(SavedGroups::default())),
+            experiments: Arc::new(RwLock::new(vec![])),
+            payload: Arc::new(RwLock::new(None)),
+            etag: Arc::new(RwLock::new(None)),
+            last_modified: Arc::new(RwLock::new(None)),
+            retry_policy: RetryPolicy::default(),
+            headers: vec![],
+            transport: default_transport(),
+            cache: None,
+            cache_max_stale_seconds: 86400,
+            cache_saved_at: Arc::new(RwLock::new(0)),
+            refreshing: Arc::new(AtomicBool::new(false)),
+            refresh_status: Arc::new(RwLock::new(RepositoryStatus::default())),
+            remote_eval_cache: Arc::new(RwLock::new(HashMap::new())),
+            remote_eval_cache_size: 1000,
         }
     }
 }
 
+#[cfg(feature = "reqwest")]
+fn default_transport() -> Option<Arc<dyn FeatureTransport>> {
+    Some(Arc::new(ReqwestTransport::default()))
+}
+
+#[cfg(not(feature = "reqwest"))]
+fn default_transport() -> Option<Arc<dyn FeatureTransport>> {
+    None
+}
+
 impl FeatureRepository {
-    fn is_cache_expired(&self) -> bool {
+    // whether a read should fetch: the features are past their ttl and no failure backoff is running
+    pub(crate) fn is_refresh_due(&self) -> bool {
+        if !self.is_cache_expired() {
+            return false;
+        }
+        let next_attempt_at = self.status().next_attempt_at.unwrap_or_default();
+        chrono::Utc::now().timestamp() >= next_attempt_at
+    }
+
+    pub(crate) fn is_cache_expired(&self) -> bool {
         match self.refreshed_at.read() {
             Ok(refreshed_at) => {
                 let expiration_time = *refreshed_at + self.ttl_seconds;
@@ -73,388 +263,2004 @@ impl FeatureRepository {
         }
     }
 
+    // like a refresh callback, but also told which features were added, removed or modified
+    pub fn add_diff_callback(&mut self, callback: FeatureDiffCallback) {
+        match self.diff_callbacks.write() {
+            Ok(mut diff_callbacks) => diff_callbacks.push(callback),
+            Err(e) => error!("Error adding diff callback: {}", e),
+        }
+    }
+
+    pub fn clear_diff_callbacks(&mut self) {
+        match self.diff_callbacks.write() {
+            Ok(mut diff_callbacks) => diff_callbacks.clear(),
+            Err(_) => error!("Error clearing diff callbacks"),
+        }
+    }
+
+    // the snapshot is shared, so this is cheap enough to call per request and hand to Context.features
     #[cfg(feature = "tokio")]
-    pub async fn get_features(&mut self) -> FeatureMap {
-        if self.is_cache_expired() {
+    pub async fn get_features(&mut self) -> Arc<FeatureMap> {
+        if self.is_refresh_due() && !self.refreshing.load(Ordering::SeqCst) && !self.load_cached_features().await {
             let mut self_clone = self.clone();
             tokio::spawn(async move {
                 self_clone.load_features(self_clone.timeout).await;
             });
         }
-        match self.features.read() {
-            Ok(features) => features.clone(),
-            Err(e) => {
-                error!("Error reading features: {}", e);
-                FeatureMap::default()
-            }
-        }
+        self.current_features()
     }
 
     #[cfg(not(feature = "tokio"))]
-    pub async fn get_features(&mut self) -> FeatureMap {
-        if self.is_cache_expired() {
+    pub async fn get_features(&mut self) -> Arc<FeatureMap> {
+        if self.is_refresh_due() && !self.load_cached_features().await {
             let mut self_clone = self.clone();
             self_clone.load_features(self_clone.timeout).await;
         }
+        self.current_features()
+    }
+
+    pub(crate) fn current_features(&self) -> Arc<FeatureMap> {
         match self.features.read() {
             Ok(features) => features.clone(),
             Err(e) => {
                 error!("Error reading features: {}", e);
-                FeatureMap::default()
+                Arc::new(FeatureMap::default())
             }
         }
     }
 
-    async fn load_features(&mut self, _timeout_seconds: u64) {
-        let mut refreshed = false;
-        if let Some(key) = &self.client_key {
-            let url = format!("{}/api/features/{}", self.api_host, key);
-            let client = ClientBuilder::new().build().unwrap_or_else(|e| {
-                error!("Error creating HTTP client: {}", e);
-                Client::new()
-            });
+    // pass these on in Context.saved_groups, refreshed together with the features
+    pub fn get_saved_groups(&self) -> SavedGroups {
+        match self.saved_groups.read() {
+            Ok(saved_groups) => saved_groups.clone(),
+            Err(e) => {
+                error!("Error reading saved groups: {}", e);
+                SavedGroups::default()
+            }
+        }
+    }
 
-            let res = match client
-                .get(url)
-                .header(USER_AGENT, format!("growthbook-sdk-rust/{}", SDK_VERSION))
-                .send()
-                .await
-            {
-                Ok(res) => res.json().await.unwrap_or_else(|e| {
-                    error!("Error parsing features: {}", e);
-                    json!({ "features": {} })
-                }),
-                Err(e) => {
-                    error!("Error fetching features: {}", e);
-                    json!({ "features": {} })
-                }
-            };
+    pub fn get_experiments(&self) -> Vec<Experiment> {
+        match self.experiments.read() {
+            Ok(experiments) => experiments.clone(),
+            Err(e) => {
+                error!("Error reading experiments: {}", e);
+                vec![]
+            }
+        }
+    }
 
-            if let Some(encrypted) = res.get("encryptedFeatures").and_then(Value::as_str) {
-                if let Some(decryption_key) = &self.decryption_key {
-                    if let Some(features) = util::decrypt_string(encrypted, decryption_key) {
-                        match self.features.write() {
-                            Ok(mut self_features) => {
-                                *self_features = serde_json::from_str(&features).unwrap_or_else(|e| {
-                                    error!("Error parsing features: {}", e);
-                                    FeatureMap::default()
-                                })
-                            }
-                            Err(_) => {
-                                error!("Error writing features")
-                            }
-                        }
-                        refreshed = true;
-                    } else {
-                        error!("Error decrypting features");
-                    }
-                } else {
-                    warn!("Decryption key not set, but found encrypted features");
-                }
-            } else if let Some(features) = res.get("features") {
-                match self.features.write() {
-                    Ok(mut self_features) => {
-                        *self_features = serde_json::from_value(features.clone()).unwrap_or_else(|e| {
-                            error!("Error parsing features: {}", e);
-                            FeatureMap::default()
-                        })
-                    }
-                    Err(_) => {
-                        error!("Error writing features")
-                    }
-                }
-                refreshed = true;
-            } else {
-                warn!("No features found");
+    // fetches right away regardless of ttl_seconds, failures are returned as well as passed to the error callbacks
+    pub async fn refresh(&mut self) -> Result<RefreshOutcome, FeatureRepositoryError> {
+        let timeout = self.timeout;
+        self.load_features_with(timeout, wait).await
+    }
+
+    async fn load_features(&mut self, timeout_seconds: u64) {
+        let _ = self.load_features_with(timeout_seconds, wait).await;
+    }
+
+    // failures are retried according to retry_policy, the features already held are kept if every attempt fails.
+    // only one load runs at a time across clones, a call made while another is in flight returns straight away.
+    // `wait` sleeps between retries and returns false when retrying should stop
+    pub(crate) async fn load_features_with<W, F>(&mut self, timeout_seconds: u64, wait: W) -> Result<RefreshOutcome, FeatureRepositoryError>
+    where
+        W: Fn(Duration) -> F,
+        F: Future<Output = bool>,
+    {
+        let _guard = match RefreshGuard::acquire(&self.refreshing) {
+            Some(guard) => guard,
+            None => return Ok(RefreshOutcome::InProgress),
+        };
+        self.update_status(|status| status.last_attempt_at = Some(chrono::Utc::now().timestamp()));
+        let result = self.fetch_with_retries(timeout_seconds, wait).await;
+        match &result {
+            Ok(_) => self.record_success(),
+            Err(e) => {
+                self.record_failure(e);
+                self.report_error(e);
             }
-        } else {
-            warn!("Client key not set");
-        }
-        if refreshed {
-            match self.refresh_callbacks.read() {
-                Ok(callbacks) => {
-                    for callback in callbacks.iter() {
-                        match self.features.read() {
-                            Ok(features) => {
-                                (callback.0)(&features);
-                            }
-                            Err(_) => {
-                                error!("Error reading features for refresh callbacks")
-                            }
-                        }
+        }
+        result
+    }
+
+    async fn fetch_with_retries<W, F>(&mut self, timeout_seconds: u64, wait: W) -> Result<RefreshOutcome, FeatureRepositoryError>
+    where
+        W: Fn(Duration) -> F,
+        F: Future<Output = bool>,
+    {
+        let key = self.client_key.as_ref().ok_or(FeatureRepositoryError::MissingClientKey)?;
+        let transport = self.transport.clone().ok_or(FeatureRepositoryError::MissingTransport)?;
+        let url = format!("{}/api/features/{}", self.api_host, key);
+
+        let max_attempts = self.retry_policy.max_attempts.max(1);
+        let mut attempt = 1;
+        loop {
+            let failure = match self.fetch_features(transport.as_ref(), &url, timeout_seconds).await {
+                Ok(None) => {
+                    self.touch_refreshed_at();
+                    self.touch_cache().await;
+                    return Ok(RefreshOutcome::NotModified);
+                }
+                Ok(Some((payload, etag, last_modified))) => {
+                    let outcome = self.apply_payload(&payload).await?;
+                    // validators of an outdated payload would only get it confirmed again
+                    if outcome != RefreshOutcome::Outdated {
+                        write_validator(&self.etag, etag);
+                        write_validator(&self.last_modified, last_modified);
                     }
+                    return Ok(outcome);
                 }
-                Err(_) => {
-                    error!("Error reading refresh callbacks")
+                Err(failure) => failure,
+            };
+
+            if !failure.retryable || attempt >= max_attempts {
+                warn!("Giving up fetching features after {} attempt(s), keeping last features", attempt);
+                if let Some(retry_after) = failure.retry_after {
+                    self.defer_next_attempt(retry_after);
                 }
+                return Err(failure.error);
             }
-
-            match self.refreshed_at.write() {
-                Ok(mut refreshed_at) => *refreshed_at = chrono::Utc::now().timestamp(),
-                Err(_) => {
-                    error!("Error setting last refresh time")
+            let delay = match failure.retry_after {
+                Some(retry_after) if retry_after > self.retry_policy.max_delay => {
+                    warn!(
+                        "Server asked to retry after {:?}, beyond the retry policy, keeping last features until then",
+                        retry_after
+                    );
+                    self.defer_next_attempt(retry_after);
+                    return Err(failure.error);
                 }
+                Some(retry_after) => retry_after,
+                None => self.retry_policy.delay_for_attempt(attempt),
+            };
+            warn!("Error fetching features, retrying in {:?}: {}", delay, failure.error);
+            if !wait(delay).await {
+                return Err(failure.error);
             }
+            attempt += 1;
         }
     }
-}
 
-#[cfg(test)]
-mod mock_features {
-    pub(crate) const UNENCRYPTED_FEATURES: &str = r#"
-        {
-            "status": 200,
-            "features": {
-              "banner_text": {
-                "defaultValue": "Welcome to Acme Donuts!",
-                "rules": [
-                  {
-                    "condition": {
-                      "country": "france"
-                    },
-                    "force": "Bienvenue au Beignets Acme !"
-                  },
-                  {
-                    "condition": {
-                      "country": "spain"
-                    },
-                    "force": "¡Bienvenidos y bienvenidas a Donas Acme!"
-                  }
-                ]
-              },
-              "dark_mode": {
-                "defaultValue": false,
-                "rules": [
-                  {
-                    "condition": {
-                      "loggedIn": true
-                    },
-                    "force": true,
-                    "coverage": 0.5,
-                    "hashAttribute": "id"
-                  }
-                ]
-              },
-              "donut_price": {
-                "defaultValue": 2.5,
-                "rules": [
-                  {
-                    "condition": {
-                      "employee": true
-                    },
-                    "force": 0
-                  }
-                ]
-              },
-              "meal_overrides_gluten_free": {
-                "defaultValue": {
-                  "meal_type": "standard",
-                  "dessert": "Strawberry Cheesecake"
-                },
-                "rules": [
-                  {
-                    "condition": {
-                      "dietaryRestrictions": {
-                        "$elemMatch": {
-                          "$eq": "gluten_free"
-                        }
-                      }
-                    },
-                    "force": {
-                      "meal_type": "gf",
-                      "dessert": "French Vanilla Ice Cream"
-                    }
-                  }
-                ]
-              },
-              "app_name": {
-                "defaultValue": "(unknown)",
-                "rules": [
-                  {
-                    "condition": {
-                      "version": {
-                        "$vgte": "1.0.0",
-                        "$vlt": "2.0.0"
-                      }
-                    },
-                    "force": "Albatross"
-                  },
-                  {
-                    "condition": {
-                      "version": {
-                        "$vgte": "2.0.0",
-                        "$vlt": "3.0.0"
-                      }
-                    },
-                    "force": "Badger"
-                  },
-                  {
-                    "condition": {
-                      "version": {
-                        "$vgte": "3.0.0",
-                        "$vlt": "4.0.0"
-                      }
-                    },
-                    "force": "Capybara"
-                  }
-                ]
-              },
-              "random_string": {
-                "defaultValue": "changed 32"
-              },
-              "greeting": {
-                "defaultValue": "hello, world!"
-              }
-            },
-            "dateUpdated": "2023-08-02T19:11:46.550Z"
-        }          
-        "#;
-    pub(crate) const ENCRYPTED_FEATURES: &str = r#"
-        {
-            "status": 200,
-            "features": {},
-            "dateUpdated": "2023-07-28T23:16:59.618Z",
-            "encryptedFeatures": "UqANSnJ7xTTK9y2PALtnwQ==.BZAstXrI9eh9qlvp7VinD8CKk9ZE8755vnFtkClJNYstTUwF4FKwWWq84F/DFTe+2Xlzbys83S1Ih6XIFhoigKIQeImlnzR3GJ6Bvj3REbKccw9TJz4bX3ozFzSNBbZbLAynnd9aTLK0PAYASLXKtIaAs/K0WSbV7mM95CVMt9DU7w1TKme/tQcqfEn+CJhi2WHNdEzGs18j9t7zXcRgdAvXizLzP7HdOnCmfXy9bZbpqWmAdUBZ0yhmb2PGXa5FBwet7h1MV0kRFX++WocwjA=="
-        }        
-        "#;
-}
+    fn request(&self, request: FeatureRequest) -> FeatureRequest {
+        self.headers
+            .iter()
+            .fold(request.with_header("User-Agent", &user_agent()), |request, (name, value)| {
+                request.with_header(name, value)
+            })
+    }
 
-#[cfg(test)]
-mod tests {
-    use std::time::Duration;
+    // Ok(None) means the server answered 304 Not Modified
+    async fn fetch_features(
+        &self,
+        transport: &dyn FeatureTransport,
+        url: &str,
+        timeout_seconds: u64,
+    ) -> Result<Option<(Value, Option<String>, Option<String>)>, FetchFailure> {
+        let mut request = self.request(FeatureRequest::get(url, Duration::from_secs(timeout_seconds)));
+        if let Some(etag) = read_validator(&self.etag) {
+            request = request.with_header("If-None-Match", &etag);
+        }
+        if let Some(last_modified) = read_validator(&self.last_modified) {
+            request = request.with_header("If-Modified-Since", &last_modified);
+        }
 
-    use tokio::time::sleep;
+        let res = transport.send(request).await.map_err(|e| FetchFailure {
+            error: FeatureRepositoryError::Transport(e),
+            retryable: true,
+            retry_after: None,
+        })?;
+        if res.status == 304 {
+            return Ok(None);
+        }
+        if !(200..300).contains(&res.status) {
+            let retry_after = match res.status {
+                429 | 503 => res.header("Retry-After").and_then(parse_retry_after),
+                _ => None,
+            };
+            return Err(FetchFailure {
+                error: FeatureRepositoryError::Status(res.status),
+                retryable: res.status == 429 || res.status >= 500,
+                retry_after,
+            });
+        }
 
-    use super::*;
+        let payload = serde_json::from_str(&res.body).map_err(|e| FetchFailure {
+            error: FeatureRepositoryError::InvalidPayload(e.to_string()),
+            retryable: false,
+            retry_after: None,
+        })?;
+        let etag = res.header("ETag").map(str::to_string);
+        let last_modified = res.header("Last-Modified").map(str::to_string);
+        Ok(Some((payload, etag, last_modified)))
+    }
 
-    async fn wait_for_refresh(gb: &mut FeatureRepository) {
-        let mut timeout = 1000;
-        loop {
-            if *gb.refreshed_at.read().unwrap() > 0 {
-                break;
+    // a cached payload newer than the features held is applied, and when it is also within ttl_seconds
+    // another instance fetched it recently, so returns true and the network fetch is skipped.
+    // older payloads up to cache_max_stale_seconds are still served until the fetch succeeds
+    pub(crate) async fn load_cached_features(&self) -> bool {
+        let (cache, key) = match (&self.cache, &self.client_key) {
+            (Some(cache), Some(key)) => (cache.clone(), key),
+            _ => return false,
+        };
+        let cached = match cache.get(key).await {
+            Ok(Some(cached)) => cached,
+            Ok(None) => return false,
+            Err(e) => {
+                self.report_error(&FeatureRepositoryError::Cache(e));
+                return false;
             }
-            if timeout > 0 {
-                sleep(Duration::from_millis(10)).await;
-                timeout -= 10;
-            } else {
-                println!("timeout waiting for refresh");
-                break;
+        };
+
+        let age = chrono::Utc::now().timestamp() - cached.saved_at;
+        if age > self.cache_max_stale_seconds || cached.saved_at <= read_timestamp(&self.cache_saved_at) {
+            return false;
+        }
+        match self.apply_features(&cached.payload, PayloadSource::Cache) {
+            Ok(RefreshOutcome::Outdated) => {
+                write_timestamp(&self.cache_saved_at, cached.saved_at);
+                return false;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                self.report_error(&e);
+                return false;
             }
         }
+        write_timestamp(&self.cache_saved_at, cached.saved_at);
+        if age <= self.ttl_seconds {
+            write_timestamp(&self.refreshed_at, cached.saved_at);
+            self.record_success();
+            return true;
+        }
+        false
     }
 
-    #[tokio::test]
-    async fn test_load_features_normal() {
-        let mut mock_server = mockito::Server::new();
-        mock_server
-            .mock("GET", "/api/features/key_for_unencrypted_features")
-            .with_status(200)
-            .with_header("content-type", "application/json")
-            .with_body(mock_features::UNENCRYPTED_FEATURES)
-            .create();
-        let mut gb = FeatureRepository {
-            api_host: mock_server.url(),
-            client_key: Some("key_for_unencrypted_features".to_string()),
-            ..Default::default()
+    // cache failures are reported but never fail a refresh, the features are already applied by then
+    async fn save_to_cache(&self, payload: CachedPayload) {
+        if let (Some(cache), Some(key)) = (&self.cache, &self.client_key) {
+            let saved_at = payload.saved_at;
+            match cache.set(key, payload).await {
+                Ok(()) => write_timestamp(&self.cache_saved_at, saved_at),
+                Err(e) => self.report_error(&FeatureRepositoryError::Cache(e)),
+            }
+        }
+    }
+
+    // a 304 confirms the cached payload is still current, so it is saved again with a fresh timestamp
+    async fn touch_cache(&self) {
+        if let (Some(cache), Some(key)) = (&self.cache, &self.client_key) {
+            match cache.get(key).await {
+                Ok(Some(cached)) => self.save_to_cache(CachedPayload::now(cached.payload)).await,
+                Ok(None) => {}
+                Err(e) => self.report_error(&FeatureRepositoryError::Cache(e)),
+            }
+        }
+    }
+
+    // seeds the features from a payload shipped with the application, refreshes still happen as usual
+    pub fn bootstrap(&self, payload: &Value) -> Result<RefreshOutcome, FeatureRepositoryError> {
+        self.apply_features(payload, PayloadSource::Bootstrap)
+    }
+
+    pub fn status(&self) -> RepositoryStatus {
+        let mut status = match self.refresh_status.read() {
+            Ok(status) => status.clone(),
+            Err(_) => {
+                error!("Error reading repository status");
+                RepositoryStatus::default()
+            }
+        };
+        status.feature_count = match self.features.read() {
+            Ok(features) => features.len(),
+            Err(_) => 0,
+        };
+        status
+    }
+
+    fn update_status(&self, update: impl FnOnce(&mut RepositoryStatus)) {
+        match self.refresh_status.write() {
+            Ok(mut status) => update(&mut status),
+            Err(_) => error!("Error writing repository status"),
+        }
+    }
+
+    fn record_success(&self) {
+        self.update_status(|status| {
+            status.last_success_at = Some(chrono::Utc::now().timestamp());
+            status.next_attempt_at = None;
+            status.last_error = None;
+            status.consecutive_failures = 0;
+        });
+    }
+
+    // reads back off after a failed refresh, so an outage doesn't turn every get_features() into a fetch
+    fn record_failure(&self, e: &FeatureRepositoryError) {
+        let mut consecutive_failures = 0;
+        self.update_status(|status| {
+            status.last_error = Some(e.clone());
+            status.consecutive_failures += 1;
+            consecutive_failures = status.consecutive_failures;
+        });
+        self.defer_next_attempt(self.retry_policy.failure_backoff_for(consecutive_failures));
+    }
+
+    // never brings an already later next attempt forward, e.g. one set from Retry-After
+    fn defer_next_attempt(&self, delay: Duration) {
+        let next_attempt_at = chrono::Utc::now().timestamp() + delay.as_secs_f64().ceil() as i64;
+        self.update_status(|status| {
+            status.next_attempt_at = Some(status.next_attempt_at.unwrap_or_default().max(next_attempt_at));
+        });
+    }
+
+    // the payload and status are updated before the features, so callbacks see them already.
+    // a payload older than the one held, going by dateUpdated, never replaces it. a slow cdn edge or
+    // stale proxy would otherwise roll the features back. payloads without dateUpdated are always applied.
+    // the check and the swap happen under the payload lock, so a concurrent apply can't slip in between
+    fn apply_features(&self, payload: &Value, source: PayloadSource) -> Result<RefreshOutcome, FeatureRepositoryError> {
+        let features = self.decode_features(payload)?;
+        let saved_groups = self.decode_saved_groups(payload)?;
+        let experiments = self.decode_experiments(payload)?;
+
+        let date_updated = payload.get("dateUpdated").and_then(Value::as_str).map(str::to_string);
+        let (diff, others_changed) = {
+            let mut held_payload = self.payload.write().map_err(|_| FeatureRepositoryError::LockPoisoned)?;
+            let held_date_updated = held_payload.as_ref().and_then(|held| held.get("dateUpdated")).and_then(Value::as_str);
+            if is_older(date_updated.as_deref(), held_date_updated) {
+                warn!(
+                    "Ignoring features updated at {} as the ones held are newer ({})",
+                    date_updated.unwrap_or_default(),
+                    held_date_updated.unwrap_or_default()
+                );
+                return Ok(RefreshOutcome::Outdated);
+            }
+            *held_payload = Some(payload.clone());
+            self.update_status(|status| {
+                status.date_updated = date_updated;
+                status.source = Some(source);
+            });
+            self.store_features(features, saved_groups, experiments)?
+        };
+        let changed = !diff.is_empty() || others_changed;
+        if changed {
+            self.notify_changes(&diff);
+        }
+        Ok(if changed { RefreshOutcome::Updated } else { RefreshOutcome::Unchanged })
+    }
+
+    // an outdated payload still counts as a successful fetch, but is kept out of the cache
+    async fn apply_payload(&self, payload: &Value) -> Result<RefreshOutcome, FeatureRepositoryError> {
+        let outcome = self.apply_features(payload, PayloadSource::Network)?;
+        self.touch_refreshed_at();
+        if outcome != RefreshOutcome::Outdated {
+            self.save_to_cache(CachedPayload::now(payload.clone())).await;
+        }
+        Ok(outcome)
+    }
+
+    // for payloads pushed to us rather than fetched, e.g. over the stream or by a webhook
+    #[cfg(any(all(feature = "tokio", feature = "reqwest"), feature = "webhook"))]
+    pub(crate) async fn receive_payload(&self, payload: &Value) -> Result<RefreshOutcome, FeatureRepositoryError> {
+        let result = self.apply_payload(payload).await;
+        match &result {
+            Ok(_) => self.record_success(),
+            Err(e) => {
+                self.record_failure(e);
+                self.report_error(e);
+            }
+        }
+        result
+    }
+
+    pub fn set_decryption_key(&self, decryption_key: Option<String>) {
+        match self.decryption_key.write() {
+            Ok(mut current) => *current = decryption_key,
+            Err(_) => error!("Error writing decryption key"),
+        }
+    }
+
+    fn decryption_key(&self) -> Option<String> {
+        match self.decryption_key.read() {
+            Ok(decryption_key) => decryption_key.clone(),
+            Err(_) => {
+                error!("Error reading decryption key");
+                None
+            }
+        }
+    }
+
+    fn decode_features(&self, payload: &Value) -> Result<FeatureMap, FeatureRepositoryError> {
+        if let Some(encrypted) = payload.get("encryptedFeatures").and_then(Value::as_str) {
+            let decryption_key = self.decryption_key().ok_or(FeatureRepositoryError::MissingDecryptionKey)?;
+            let features = util::decrypt_string(encrypted, &decryption_key).ok_or(FeatureRepositoryError::Decryption)?;
+            serde_json::from_str(&features).map_err(|e| FeatureRepositoryError::InvalidPayload(e.to_string()))
+        } else if let Some(features) = payload.get("features") {
+            serde_json::from_value(features.clone()).map_err(|e| FeatureRepositoryError::InvalidPayload(e.to_string()))
+        } else {
+            Err(FeatureRepositoryError::InvalidPayload("no features found".to_string()))
+        }
+    }
+
+    // saved groups and experiments are optional, older payloads simply have none
+    fn decode_saved_groups(&self, payload: &Value) -> Result<SavedGroups, FeatureRepositoryError> {
+        self.decode_optional(payload, "savedGroups", "encryptedSavedGroups")
+    }
+
+    // url targets are compiled here once, instead of on every evaluation
+    fn decode_experiments(&self, payload: &Value) -> Result<Vec<Experiment>, FeatureRepositoryError> {
+        let experiments: Vec<Experiment> = self.decode_optional(payload, "experiments", "encryptedExperiments")?;
+        for experiment in &experiments {
+            util::compile_url_targets(&experiment.url_patterns);
+        }
+        Ok(experiments)
+    }
+
+    fn decode_optional<T: DeserializeOwned + Default>(
+        &self,
+        payload: &Value,
+        field: &str,
+        encrypted_field: &str,
+    ) -> Result<T, FeatureRepositoryError> {
+        if let Some(encrypted) = payload.get(encrypted_field).and_then(Value::as_str) {
+            let decryption_key = self.decryption_key().ok_or(FeatureRepositoryError::MissingDecryptionKey)?;
+            let decrypted = util::decrypt_string(encrypted, &decryption_key).ok_or(FeatureRepositoryError::Decryption)?;
+            serde_json::from_str(&decrypted).map_err(|e| FeatureRepositoryError::InvalidPayload(e.to_string()))
+        } else if let Some(value) = payload.get(field) {
+            serde_json::from_value(value.clone()).map_err(|e| FeatureRepositoryError::InvalidPayload(e.to_string()))
+        } else {
+            Ok(T::default())
+        }
+    }
+
+    // swaps in whatever differs from what is already held and returns the feature diff, plus whether the
+    // saved groups or experiments changed. those alone reach the refresh callbacks but not the diff callbacks
+    fn store_features(
+        &self,
+        features: FeatureMap,
+        saved_groups: SavedGroups,
+        experiments: Vec<Experiment>,
+    ) -> Result<(FeatureDiff, bool), FeatureRepositoryError> {
+        let saved_groups_changed = replace_if_changed(&self.saved_groups, saved_groups)?;
+        let experiments_changed = replace_if_changed(&self.experiments, experiments)?;
+        let diff = match self.features.write() {
+            Ok(mut self_features) => {
+                let diff = FeatureDiff::between(&self_features, &features);
+                if !diff.is_empty() {
+                    *self_features = Arc::new(features);
+                }
+                diff
+            }
+            Err(_) => return Err(FeatureRepositoryError::LockPoisoned),
+        };
+        Ok((diff, saved_groups_changed || experiments_changed))
+    }
+
+    // runs without any lock held, callbacks are free to read the payload or features
+    fn notify_changes(&self, diff: &FeatureDiff) {
+        self.clear_remote_eval_cache();
+        self.notify_refresh_callbacks();
+        if !diff.is_empty() {
+            self.notify_diff_callbacks(diff);
+        }
+    }
+
+    pub fn add_error_callback(&mut self, callback: FeatureRepositoryErrorCallback) {
+        match self.error_callbacks.write() {
+            Ok(mut error_callbacks) => error_callbacks.push(callback),
+            Err(e) => error!("Error adding error callback: {}", e),
+        }
+    }
+
+    pub fn clear_error_callbacks(&mut self) {
+        match self.error_callbacks.write() {
+            Ok(mut error_callbacks) => error_callbacks.clear(),
+            Err(_) => error!("Error clearing error callbacks"),
+        }
+    }
+
+    fn report_error(&self, e: &FeatureRepositoryError) {
+        error!("Error refreshing features: {}", e);
+        match self.error_callbacks.read() {
+            Ok(callbacks) => {
+                for callback in callbacks.iter() {
+                    (callback.0)(e);
+                }
+            }
+            Err(_) => {
+                error!("Error reading error callbacks")
+            }
+        }
+    }
+
+    fn notify_refresh_callbacks(&self) {
+        // a snapshot, so no lock on the features is held while the callbacks run
+        let features = match self.features.read() {
+            Ok(features) => features.clone(),
+            Err(_) => {
+                error!("Error reading features for refresh callbacks");
+                return;
+            }
+        };
+        match self.refresh_callbacks.read() {
+            Ok(callbacks) => {
+                for callback in callbacks.iter() {
+                    (callback.0)(&features);
+                }
+            }
+            Err(_) => {
+                error!("Error reading refresh callbacks")
+            }
+        }
+    }
+
+    fn notify_diff_callbacks(&self, diff: &FeatureDiff) {
+        // a snapshot, so no lock on the features is held while the callbacks run
+        let features = match self.features.read() {
+            Ok(features) => features.clone(),
+            Err(_) => {
+                error!("Error reading features for diff callbacks");
+                return;
+            }
+        };
+        match self.diff_callbacks.read() {
+            Ok(callbacks) => {
+                for callback in callbacks.iter() {
+                    (callback.0)(&features, diff);
+                }
+            }
+            Err(_) => {
+                error!("Error reading diff callbacks")
+            }
+        }
+    }
+
+    fn touch_refreshed_at(&self) {
+        write_timestamp(&self.refreshed_at, chrono::Utc::now().timestamp());
+    }
+
+    // remote evaluation: the server evaluates the features for the context's attributes and forced variations,
+    // so targeting rules never leave it. the returned features can go straight into Context.features.
+    // results are cached per attribute set for ttl_seconds and dropped whenever refreshed features change
+    pub async fn remote_eval(&self, context: &Context) -> Result<Arc<FeatureMap>, FeatureRepositoryError> {
+        let key = self.client_key.as_ref().ok_or(FeatureRepositoryError::MissingClientKey)?;
+        let cache_key = remote_eval_body(context).to_string();
+        if let Some(features) = self.cached_remote_eval(&cache_key, false) {
+            return Ok(features);
+        }
+
+        let transport = self.transport.clone().ok_or(FeatureRepositoryError::MissingTransport)?;
+        let url = format!("{}/api/eval/{}", self.api_host, key);
+        let request = self
+            .request(FeatureRequest::post(&url, &cache_key, Duration::from_secs(self.timeout)))
+            .with_header("Content-Type", "application/json");
+        let res = transport.send(request).await.map_err(FeatureRepositoryError::Transport)?;
+        if !(200..300).contains(&res.status) {
+            return Err(FeatureRepositoryError::Status(res.status));
+        }
+        let payload: Value = serde_json::from_str(&res.body).map_err(|e| FeatureRepositoryError::InvalidPayload(e.to_string()))?;
+        let features = Arc::new(self.decode_features(&payload)?);
+
+        match self.remote_eval_cache.write() {
+            Ok(mut cache) => {
+                if cache.len() >= self.remote_eval_cache_size {
+                    // full, make room by dropping the oldest entry
+                    if let Some(oldest) = cache.iter().min_by_key(|(_, (fetched_at, _))| *fetched_at).map(|(key, _)| key.clone()) {
+                        cache.remove(&oldest);
+                    }
+                }
+                cache.insert(cache_key, (chrono::Utc::now().timestamp(), features.clone()));
+            }
+            Err(_) => error!("Error writing remote evaluation cache"),
+        }
+        Ok(features)
+    }
+
+    // never fails, falls back to the last result for the same attributes, even when past ttl_seconds
+    pub async fn get_remote_features(&self, context: &Context) -> Arc<FeatureMap> {
+        match self.remote_eval(context).await {
+            Ok(features) => features,
+            Err(e) => {
+                self.report_error(&e);
+                self.cached_remote_eval(&remote_eval_body(context).to_string(), true).unwrap_or_default()
+            }
+        }
+    }
+
+    pub fn clear_remote_eval_cache(&self) {
+        match self.remote_eval_cache.write() {
+            Ok(mut cache) => cache.clear(),
+            Err(_) => error!("Error clearing remote evaluation cache"),
+        }
+    }
+
+    fn cached_remote_eval(&self, cache_key: &str, allow_expired: bool) -> Option<Arc<FeatureMap>> {
+        match self.remote_eval_cache.read() {
+            Ok(cache) => cache
+                .get(cache_key)
+                .filter(|(fetched_at, _)| allow_expired || chrono::Utc::now().timestamp() - fetched_at <= self.ttl_seconds)
+                .map(|(_, features)| features.clone()),
+            Err(_) => {
+                error!("Error reading remote evaluation cache");
+                None
+            }
+        }
+    }
+
+    // refreshes every `interval` regardless of reads, the first refresh happens right away.
+    // stopping or dropping the returned handle also cancels a request that is still in flight
+    #[cfg(feature = "tokio")]
+    pub fn start_polling(&self, interval: Duration) -> RefreshHandle {
+        let mut repository = self.clone();
+        RefreshHandle::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                repository.load_features(repository.timeout).await;
+            }
+        })
+    }
+
+    // opt-in server-sent events mode, features are pushed by the /sub endpoint as soon as they change.
+    // the returned handle keeps the connection alive, dropping it or calling stop() closes the stream.
+    #[cfg(all(feature = "tokio", feature = "reqwest"))]
+    pub fn start_streaming(&self) -> RefreshHandle {
+        let repository = self.clone();
+        RefreshHandle::spawn(async move { repository.stream_features().await })
+    }
+
+    #[cfg(all(feature = "tokio", feature = "reqwest"))]
+    async fn stream_features(mut self) {
+        let mut backoff = STREAMING_MIN_BACKOFF;
+        loop {
+            match self.read_stream().await {
+                Ok(received) => {
+                    if received {
+                        backoff = STREAMING_MIN_BACKOFF;
+                    }
+                    warn!("Feature stream closed, reconnecting in {:?}", backoff);
+                }
+                Err(e) => {
+                    warn!("Feature stream failed, reconnecting in {:?}", backoff);
+                    self.report_error(&FeatureRepositoryError::Transport(TransportError { message: e.to_string() }));
+                }
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(STREAMING_MAX_BACKOFF);
+        }
+    }
+
+    // returns whether any event was received before the server closed the stream
+    #[cfg(all(feature = "tokio", feature = "reqwest"))]
+    async fn read_stream(&mut self) -> Result<bool, reqwest::Error> {
+        let key = match &self.client_key {
+            Some(key) => key,
+            None => {
+                warn!("Client key not set");
+                return Ok(false);
+            }
+        };
+        let url = format!("{}/sub/{}", self.api_host, key);
+        let client = self
+            .transport
+            .as_ref()
+            .and_then(|transport| transport.reqwest_client())
+            .unwrap_or_default();
+        let mut builder = client.get(url).header(USER_AGENT, user_agent()).header(ACCEPT, "text/event-stream");
+        for (name, value) in self.headers.iter() {
+            builder = builder.header(name, value);
+        }
+        let mut res = builder.send().await?.error_for_status()?;
+
+        let mut parser = SseParser::default();
+        let mut received = false;
+        while let Some(chunk) = res.chunk().await? {
+            for event in parser.push(&chunk) {
+                received = true;
+                self.handle_stream_event(&event).await;
+            }
+        }
+        Ok(received)
+    }
+
+    #[cfg(all(feature = "tokio", feature = "reqwest"))]
+    async fn handle_stream_event(&mut self, event: &SseEvent) {
+        match event.event.as_str() {
+            "features" | "features-updated" => match serde_json::from_str::<Value>(&event.data) {
+                Ok(payload) if payload.get("features").is_some() || payload.get("encryptedFeatures").is_some() => {
+                    let _ = self.receive_payload(&payload).await;
+                }
+                // an update notification without a payload, fall back to fetching it
+                _ => self.load_features(self.timeout).await,
+            },
+            _ => {}
+        }
+    }
+}
+
+// retries need a timer: the async FeatureRepository waits on tokio and BlockingFeatureRepository sleeps
+// its thread. an async FeatureRepository built without the `tokio` feature has neither, so it gives up
+// after the first failed attempt.
+// a Retry-After beyond max_delay ends the refresh right away instead, get_features() then doesn't fetch
+// again before it has passed
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    // fraction of each delay that is randomized, 0.0 disables jitter
+    pub jitter: f64,
+    // once a refresh gave up, get_features() waits this long before the next one,
+    // doubled for every further failure in a row up to max_failure_backoff
+    pub failure_backoff: Duration,
+    pub max_failure_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: 0.2,
+            failure_backoff: Duration::from_secs(5),
+            max_failure_backoff: Duration::from_secs(300),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    // exponential backoff capped at max_delay, with up to `jitter` of it taken off at random
+    pub fn delay_for_attempt(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let delay = self.base_delay.saturating_mul(2u32.pow(exponent)).min(self.max_delay);
+        delay.mul_f64(1.0 - self.jitter.clamp(0.0, 1.0) * random_fraction())
+    }
+
+    pub fn failure_backoff_for(&self, consecutive_failures: u32) -> Duration {
+        let exponent = consecutive_failures.saturating_sub(1).min(31);
+        self.failure_backoff.saturating_mul(2u32.pow(exponent)).min(self.max_failure_backoff)
+    }
+}
+
+#[derive(Debug)]
+struct FetchFailure {
+    error: FeatureRepositoryError,
+    retryable: bool,
+    retry_after: Option<Duration>,
+}
+
+// Retry-After is either a number of seconds or an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    let seconds = (date.timestamp() - chrono::Utc::now().timestamp()).max(0);
+    Some(Duration::from_secs(seconds as u64))
+}
+
+fn random_fraction() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_i64(chrono::Utc::now().timestamp_subsec_nanos() as i64);
+    (hasher.finish() % 10_000) as f64 / 10_000.0
+}
+
+#[cfg(feature = "tokio")]
+async fn wait(delay: Duration) -> bool {
+    tokio::time::sleep(delay).await;
+    true
+}
+
+// without tokio there is no timer to wait on, retrying straight away would only hammer the server
+#[cfg(not(feature = "tokio"))]
+async fn wait(_delay: Duration) -> bool {
+    static SKIPPED: std::sync::Once = std::sync::Once::new();
+    SKIPPED.call_once(|| warn!("Retries are skipped without the tokio feature, enable it or use BlockingFeatureRepository"));
+    false
+}
+
+fn read_validator(validator: &RwLock<Option<String>>) -> Option<String> {
+    match validator.read() {
+        Ok(validator) => validator.clone(),
+        Err(_) => {
+            error!("Error reading cache validator");
+            None
+        }
+    }
+}
+
+fn write_validator(validator: &RwLock<Option<String>>, value: Option<String>) {
+    match validator.write() {
+        Ok(mut validator) => *validator = value,
+        Err(_) => error!("Error writing cache validator"),
+    }
+}
+
+// marks a load as in flight until dropped, which also covers loads cancelled mid request
+struct RefreshGuard(Arc<AtomicBool>);
+
+impl RefreshGuard {
+    fn acquire(refreshing: &Arc<AtomicBool>) -> Option<Self> {
+        if refreshing.swap(true, Ordering::SeqCst) {
+            None
+        } else {
+            Some(RefreshGuard(refreshing.clone()))
+        }
+    }
+}
+
+impl Drop for RefreshGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+// timestamps that fail to parse are never considered older
+fn is_older(date_updated: Option<&str>, held_date_updated: Option<&str>) -> bool {
+    let parse = |date: Option<&str>| date.and_then(|date| chrono::DateTime::parse_from_rfc3339(date).ok());
+    match (parse(date_updated), parse(held_date_updated)) {
+        (Some(date_updated), Some(held_date_updated)) => date_updated < held_date_updated,
+        _ => false,
+    }
+}
+
+fn replace_if_changed<T: PartialEq>(current: &RwLock<T>, value: T) -> Result<bool, FeatureRepositoryError> {
+    let mut current = current.write().map_err(|_| FeatureRepositoryError::LockPoisoned)?;
+    if *current == value {
+        return Ok(false);
+    }
+    *current = value;
+    Ok(true)
+}
+
+fn read_timestamp(timestamp: &RwLock<i64>) -> i64 {
+    match timestamp.read() {
+        Ok(timestamp) => *timestamp,
+        Err(_) => {
+            error!("Error reading timestamp");
+            0
+        }
+    }
+}
+
+fn write_timestamp(timestamp: &RwLock<i64>, value: i64) {
+    match timestamp.write() {
+        Ok(mut timestamp) => *timestamp = value,
+        Err(_) => error!("Error writing timestamp"),
+    }
+}
+
+// doubles as the remote evaluation cache key, so forced variations are sorted to keep it stable
+fn remote_eval_body(context: &Context) -> Value {
+    json!({
+        "attributes": context.attributes,
+        "forcedVariations": context.forced_variations.iter().collect::<BTreeMap<_, _>>(),
+        "forcedFeatures": [],
+        "url": context.url,
+    })
+}
+
+fn user_agent() -> String {
+    format!("growthbook-sdk-rust/{}", SDK_VERSION)
+}
+
+#[cfg(all(feature = "tokio", feature = "reqwest"))]
+const STREAMING_MIN_BACKOFF: Duration = Duration::from_secs(1);
+#[cfg(all(feature = "tokio", feature = "reqwest"))]
+const STREAMING_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct RefreshHandle {
+    task: tokio::task::JoinHandle<()>,
+}
+
+#[cfg(feature = "tokio")]
+impl RefreshHandle {
+    pub(crate) fn spawn<F: Future<Output = ()> + Send + 'static>(future: F) -> Self {
+        RefreshHandle { task: tokio::spawn(future) }
+    }
+
+    pub fn stop(self) {
+        self.task.abort();
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.task.is_finished()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Drop for RefreshHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(all(test, feature = "reqwest"))]
+mod mock_features {
+    pub(crate) const UNENCRYPTED_FEATURES: &str = r#"
+        {
+            "status": 200,
+            "features": {
+              "banner_text": {
+                "defaultValue": "Welcome to Acme Donuts!",
+                "rules": [
+                  {
+                    "condition": {
+                      "country": "france"
+                    },
+                    "force": "Bienvenue au Beignets Acme !"
+                  },
+                  {
+                    "condition": {
+                      "country": "spain"
+                    },
+                    "force": "¡Bienvenidos y bienvenidas a Donas Acme!"
+                  }
+                ]
+              },
+              "dark_mode": {
+                "defaultValue": false,
+                "rules": [
+                  {
+                    "condition": {
+                      "loggedIn": true
+                    },
+                    "force": true,
+                    "coverage": 0.5,
+                    "hashAttribute": "id"
+                  }
+                ]
+              },
+              "donut_price": {
+                "defaultValue": 2.5,
+                "rules": [
+                  {
+                    "condition": {
+                      "employee": true
+                    },
+                    "force": 0
+                  }
+                ]
+              },
+              "meal_overrides_gluten_free": {
+                "defaultValue": {
+                  "meal_type": "standard",
+                  "dessert": "Strawberry Cheesecake"
+                },
+                "rules": [
+                  {
+                    "condition": {
+                      "dietaryRestrictions": {
+                        "$elemMatch": {
+                          "$eq": "gluten_free"
+                        }
+                      }
+                    },
+                    "force": {
+                      "meal_type": "gf",
+                      "dessert": "French Vanilla Ice Cream"
+                    }
+                  }
+                ]
+              },
+              "app_name": {
+                "defaultValue": "(unknown)",
+                "rules": [
+                  {
+                    "condition": {
+                      "version": {
+                        "$vgte": "1.0.0",
+                        "$vlt": "2.0.0"
+                      }
+                    },
+                    "force": "Albatross"
+                  },
+                  {
+                    "condition": {
+                      "version": {
+                        "$vgte": "2.0.0",
+                        "$vlt": "3.0.0"
+                      }
+                    },
+                    "force": "Badger"
+                  },
+                  {
+                    "condition": {
+                      "version": {
+                        "$vgte": "3.0.0",
+                        "$vlt": "4.0.0"
+                      }
+                    },
+                    "force": "Capybara"
+                  }
+                ]
+              },
+              "random_string": {
+                "defaultValue": "changed 32"
+              },
+              "greeting": {
+                "defaultValue": "hello, world!"
+              }
+            },
+            "dateUpdated": "2023-08-02T19:11:46.550Z"
+        }          
+        "#;
+    pub(crate) const ENCRYPTED_FEATURES: &str = r#"
+        {
+            "status": 200,
+            "features": {},
+            "dateUpdated": "2023-07-28T23:16:59.618Z",
+            "encryptedFeatures": "UqANSnJ7xTTK9y2PALtnwQ==.BZAstXrI9eh9qlvp7VinD8CKk9ZE8755vnFtkClJNYstTUwF4FKwWWq84F/DFTe+2Xlzbys83S1Ih6XIFhoigKIQeImlnzR3GJ6Bvj3REbKccw9TJz4bX3ozFzSNBbZbLAynnd9aTLK0PAYASLXKtIaAs/K0WSbV7mM95CVMt9DU7w1TKme/tQcqfEn+CJhi2WHNdEzGs18j9t7zXcRgdAvXizLzP7HdOnCmfXy9bZbpqWmAdUBZ0yhmb2PGXa5FBwet7h1MV0kRFX++WocwjA=="
+        }        
+        "#;
+}
+
+// these talk to a mock server, so they need the default reqwest transport
+#[cfg(all(test, feature = "reqwest"))]
+mod tests {
+    use std::sync::atomic::AtomicU32;
+    use std::time::Duration;
+
+    use tokio::time::sleep;
+
+    use super::*;
+    use crate::cache::InMemoryFeatureCache;
+    use crate::model::UrlTargetType;
+
+    async fn wait_for_refresh(gb: &mut FeatureRepository) {
+        let mut timeout = 1000;
+        loop {
+            if *gb.refreshed_at.read().unwrap() > 0 {
+                break;
+            }
+            if timeout > 0 {
+                sleep(Duration::from_millis(10)).await;
+                timeout -= 10;
+            } else {
+                println!("timeout waiting for refresh");
+                break;
+            }
+        }
+    }
+
+    async fn wait_for_mock(mock: &mockito::Mock) {
+        let mut timeout = 2000;
+        while !mock.matched_async().await && timeout > 0 {
+            sleep(Duration::from_millis(10)).await;
+            timeout -= 10;
+        }
+        // give the repository a moment to act on the last response
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    fn fast_retry_policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(10),
+            max_delay: Duration::from_secs(2),
+            jitter: 0.0,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_load_features_normal() {
+        let mut mock_server = mockito::Server::new_async().await;
+        mock_server
+            .mock("GET", "/api/features/key_for_unencrypted_features")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(mock_features::UNENCRYPTED_FEATURES)
+            .create();
+        let mut gb = FeatureRepository {
+            api_host: mock_server.url(),
+            client_key: Some("key_for_unencrypted_features".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(gb.features.read().unwrap().len(), 0);
+        gb.get_features().await;
+        wait_for_refresh(&mut gb).await;
+        assert_eq!(gb.features.read().unwrap().len(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_load_features_encrypted() {
+        let mut mock_server = mockito::Server::new_async().await;
+        mock_server
+            .mock("GET", "/api/features/key_for_encrypted_features")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(mock_features::ENCRYPTED_FEATURES)
+            .create();
+        let mut gb = FeatureRepository {
+            api_host: mock_server.url(),
+            client_key: Some("key_for_encrypted_features".to_string()),
+            decryption_key: Arc::new(RwLock::new(Some("BhB1wORFmZLTDjbvstvS8w==".to_string()))),
+            ..Default::default()
+        };
+        assert_eq!(gb.features.read().unwrap().len(), 0);
+        gb.get_features().await;
+        wait_for_refresh(&mut gb).await;
+        assert_eq!(gb.features.read().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_single_callback() {
+        static mut COUNT: u32 = 0;
+        // unsafe is fine here, just for testing
+        let callback: FeatureRefreshCallback = FeatureRefreshCallback(Box::new(move |features| unsafe {
+            assert_eq!(features.len(), 7);
+            COUNT += 1;
+        }));
+
+        let mut mock_server = mockito::Server::new_async().await;
+        mock_server
+            .mock("GET", "/api/features/key_for_unencrypted_features")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(mock_features::UNENCRYPTED_FEATURES)
+            .create();
+
+        let mut gb = FeatureRepository {
+            api_host: mock_server.url(),
+            client_key: Some("key_for_unencrypted_features".to_string()),
+            ..Default::default()
+        };
+        gb.add_refresh_callback(callback);
+        gb.get_features().await;
+        wait_for_refresh(&mut gb).await;
+        assert_eq!(unsafe { COUNT }, 1);
+    }
+
+    #[tokio::test]
+    async fn test_multiple_callback() {
+        static mut COUNT: u32 = 0;
+        // TODO: unsafe is fine here, just for testing. Still better way?
+        let callback_one: FeatureRefreshCallback = FeatureRefreshCallback(Box::new(move |features| unsafe {
+            assert_eq!(features.len(), 7);
+            COUNT += 1;
+        }));
+        let callback_two: FeatureRefreshCallback = FeatureRefreshCallback(Box::new(move |features| unsafe {
+            assert_eq!(features.len(), 7);
+            COUNT += 1;
+        }));
+
+        let mut mock_server = mockito::Server::new_async().await;
+        mock_server
+            .mock("GET", "/api/features/key_for_unencrypted_features")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(mock_features::UNENCRYPTED_FEATURES)
+            .create();
+
+        let mut gb = FeatureRepository {
+            api_host: mock_server.url(),
+            client_key: Some("key_for_unencrypted_features".to_string()),
+            ..Default::default()
+        };
+        gb.add_refresh_callback(callback_one);
+        gb.add_refresh_callback(callback_two);
+        gb.get_features().await;
+        wait_for_refresh(&mut gb).await;
+        assert_eq!(unsafe { COUNT }, 2);
+    }
+
+    #[tokio::test]
+    async fn test_clear_callback() {
+        static mut COUNT: u32 = 0;
+        // TODO: unsafe is fine here, just for testing. Still better way?
+        let callback: FeatureRefreshCallback = FeatureRefreshCallback(Box::new(move |features| unsafe {
+            assert_eq!(features.len(), 1);
+            COUNT += 1;
+        }));
+
+        let mut mock_server = mockito::Server::new_async().await;
+        mock_server
+            .mock("GET", "/api/features/key_for_encrypted_features")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(mock_features::ENCRYPTED_FEATURES)
+            .create();
+
+        let mut gb = FeatureRepository {
+            api_host: mock_server.url(),
+            client_key: Some("key_for_encrypted_features".to_string()),
+            decryption_key: Arc::new(RwLock::new(Some("BhB1wORFmZLTDjbvstvS8w==".to_string()))),
+            ..Default::default()
+        };
+        gb.add_refresh_callback(callback);
+        gb.get_features().await;
+        wait_for_refresh(&mut gb).await;
+        assert_eq!(unsafe { COUNT }, 1);
+
+        unsafe {
+            COUNT = 0;
+        }
+        *gb.refreshed_at.write().unwrap() = 0;
+        gb.clear_refresh_callbacks();
+        gb.get_features().await;
+        wait_for_refresh(&mut gb).await;
+        assert_eq!(unsafe { COUNT }, 0);
+    }
+
+    #[tokio::test]
+    async fn test_conditional_fetch_not_modified() {
+        let refreshes = Arc::new(AtomicU32::new(0));
+        let callback_refreshes = refreshes.clone();
+        let callback: FeatureRefreshCallback = FeatureRefreshCallback(Box::new(move |features| {
+            assert_eq!(features.len(), 7);
+            callback_refreshes.fetch_add(1, Ordering::SeqCst);
+        }));
+
+        let mut mock_server = mockito::Server::new_async().await;
+        mock_server
+            .mock("GET", "/api/features/key_for_conditional_fetch")
+            .match_header("if-none-match", mockito::Matcher::Missing)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_header("etag", "\"v1\"")
+            .with_header("last-modified", "Wed, 02 Aug 2023 19:11:46 GMT")
+            .with_body(mock_features::UNENCRYPTED_FEATURES)
+            .create();
+        let not_modified = mock_server
+            .mock("GET", "/api/features/key_for_conditional_fetch")
+            .match_header("if-none-match", "\"v1\"")
+            .match_header("if-modified-since", "Wed, 02 Aug 2023 19:11:46 GMT")
+            .with_status(304)
+            .create();
+
+        let mut gb = FeatureRepository {
+            api_host: mock_server.url(),
+            client_key: Some("key_for_conditional_fetch".to_string()),
+            ..Default::default()
+        };
+        gb.add_refresh_callback(callback);
+        gb.get_features().await;
+        wait_for_refresh(&mut gb).await;
+        assert_eq!(*gb.etag.read().unwrap(), Some("\"v1\"".to_string()));
+        assert_eq!(refreshes.load(Ordering::SeqCst), 1);
+
+        *gb.refreshed_at.write().unwrap() = 0;
+        gb.get_features().await;
+        wait_for_refresh(&mut gb).await;
+        not_modified.assert_async().await;
+        assert_eq!(gb.features.read().unwrap().len(), 7);
+        assert_eq!(refreshes.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unchanged_payload_skips_callbacks() {
+        let refreshes = Arc::new(AtomicU32::new(0));
+        let callback_refreshes = refreshes.clone();
+        let callback: FeatureRefreshCallback = FeatureRefreshCallback(Box::new(move |_features| {
+            callback_refreshes.fetch_add(1, Ordering::SeqCst);
+        }));
+
+        let mut mock_server = mockito::Server::new_async().await;
+        let mock = mock_server
+            .mock("GET", "/api/features/key_for_unencrypted_features")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(mock_features::UNENCRYPTED_FEATURES)
+            .expect(2)
+            .create();
+
+        let mut gb = FeatureRepository {
+            api_host: mock_server.url(),
+            client_key: Some("key_for_unencrypted_features".to_string()),
+            ..Default::default()
+        };
+        gb.add_refresh_callback(callback);
+        gb.get_features().await;
+        wait_for_refresh(&mut gb).await;
+        assert_eq!(refreshes.load(Ordering::SeqCst), 1);
+
+        *gb.refreshed_at.write().unwrap() = 0;
+        gb.get_features().await;
+        wait_for_refresh(&mut gb).await;
+        mock.assert_async().await;
+        assert_eq!(refreshes.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_stale_cache_is_served_while_offline() {
+        let cache = InMemoryFeatureCache::default();
+        let payload: Value = serde_json::from_str(mock_features::ENCRYPTED_FEATURES).unwrap();
+        let saved_at = chrono::Utc::now().timestamp() - 600;
+        cache.set("key_for_offline_start", CachedPayload { saved_at, payload }).await.unwrap();
+
+        let mut mock_server = mockito::Server::new_async().await;
+        let unavailable = mock_server
+            .mock("GET", "/api/features/key_for_offline_start")
+            .with_status(503)
+            .expect_at_least(1)
+            .create();
+
+        let mut gb = FeatureRepository {
+            api_host: mock_server.url(),
+            client_key: Some("key_for_offline_start".to_string()),
+            decryption_key: Arc::new(RwLock::new(Some("BhB1wORFmZLTDjbvstvS8w==".to_string()))),
+            retry_policy: RetryPolicy::none(),
+            cache: Some(Arc::new(cache)),
+            ..Default::default()
+        };
+        assert_eq!(gb.get_features().await.len(), 1);
+        wait_for_mock(&unavailable).await;
+        unavailable.assert_async().await;
+        assert_eq!(gb.features.read().unwrap().len(), 1);
+        assert_eq!(*gb.refreshed_at.read().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_expired_cache_is_discarded() {
+        let cache = InMemoryFeatureCache::default();
+        let payload: Value = serde_json::from_str(mock_features::UNENCRYPTED_FEATURES).unwrap();
+        let saved_at = chrono::Utc::now().timestamp() - 7200;
+        cache.set("key_for_expired_cache", CachedPayload { saved_at, payload }).await.unwrap();
+
+        let mut gb = FeatureRepository {
+            client_key: Some("key_for_expired_cache".to_string()),
+            transport: None,
+            cache: Some(Arc::new(cache)),
+            cache_max_stale_seconds: 3600,
+            ..Default::default()
+        };
+        assert_eq!(gb.get_features().await.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_fresh_cache_skips_network() {
+        let cache = InMemoryFeatureCache::default();
+        let payload: Value = serde_json::from_str(mock_features::UNENCRYPTED_FEATURES).unwrap();
+        cache.set("key_for_shared_cache", CachedPayload::now(payload)).await.unwrap();
+
+        let mut mock_server = mockito::Server::new_async().await;
+        let network = mock_server.mock("GET", "/api/features/key_for_shared_cache").expect(0).create();
+
+        let mut gb = FeatureRepository {
+            api_host: mock_server.url(),
+            client_key: Some("key_for_shared_cache".to_string()),
+            cache: Some(Arc::new(cache)),
+            ..Default::default()
+        };
+        assert_eq!(gb.get_features().await.len(), 7);
+        sleep(Duration::from_millis(100)).await;
+        network.assert_async().await;
+        assert!(!gb.is_cache_expired());
+        assert_eq!(gb.status().source, Some(PayloadSource::Cache));
+    }
+
+    #[tokio::test]
+    async fn test_fetched_payload_is_written_to_cache() {
+        let cache = InMemoryFeatureCache::default();
+        let mut mock_server = mockito::Server::new_async().await;
+        mock_server
+            .mock("GET", "/api/features/key_for_encrypted_features")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(mock_features::ENCRYPTED_FEATURES)
+            .create();
+
+        let mut gb = FeatureRepository {
+            api_host: mock_server.url(),
+            client_key: Some("key_for_encrypted_features".to_string()),
+            decryption_key: Arc::new(RwLock::new(Some("BhB1wORFmZLTDjbvstvS8w==".to_string()))),
+            cache: Some(Arc::new(cache.clone())),
+            ..Default::default()
+        };
+        gb.get_features().await;
+        wait_for_refresh(&mut gb).await;
+        sleep(Duration::from_millis(50)).await;
+
+        let cached = cache.get("key_for_encrypted_features").await.unwrap().unwrap();
+        assert_eq!(cached.payload, serde_json::from_str::<Value>(mock_features::ENCRYPTED_FEATURES).unwrap());
+        assert!(cached.payload.get("encryptedFeatures").is_some());
+    }
+
+    // answers after a delay and counts how many requests were started
+    #[cfg(feature = "tokio")]
+    #[derive(Debug, Default)]
+    struct SlowTransport {
+        requests: std::sync::atomic::AtomicUsize,
+    }
+
+    #[cfg(feature = "tokio")]
+    #[async_trait::async_trait]
+    impl FeatureTransport for SlowTransport {
+        async fn send(&self, _request: FeatureRequest) -> Result<crate::transport::FeatureResponse, crate::transport::TransportError> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            sleep(Duration::from_millis(200)).await;
+            Ok(crate::transport::FeatureResponse {
+                status: 200,
+                headers: vec![],
+                body: mock_features::UNENCRYPTED_FEATURES.to_string(),
+            })
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_concurrent_reads_start_one_refresh() {
+        let transport = Arc::new(SlowTransport::default());
+        let mut gb = FeatureRepository {
+            client_key: Some("key_for_concurrent_reads".to_string()),
+            transport: Some(transport.clone()),
+            ..Default::default()
+        };
+        for _ in 0..5 {
+            gb.clone().get_features().await;
+        }
+        wait_for_refresh(&mut gb).await;
+        assert_eq!(transport.requests.load(Ordering::SeqCst), 1);
+        assert_eq!(gb.features.read().unwrap().len(), 7);
+        assert!(!gb.refreshing.load(Ordering::SeqCst));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_polling_refreshes_until_stopped() {
+        let mut mock_server = mockito::Server::new_async().await;
+        let mock = mock_server
+            .mock("GET", "/api/features/key_for_polling")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(mock_features::UNENCRYPTED_FEATURES)
+            .expect_at_least(3)
+            .create();
+
+        let gb = FeatureRepository {
+            api_host: mock_server.url(),
+            client_key: Some("key_for_polling".to_string()),
+            ..Default::default()
+        };
+        let handle = gb.start_polling(Duration::from_millis(100));
+        sleep(Duration::from_millis(350)).await;
+        assert!(handle.is_running());
+        handle.stop();
+        mock.assert_async().await;
+        assert_eq!(gb.features.read().unwrap().len(), 7);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_dropping_polling_handle_cancels_in_flight_refresh() {
+        let transport = Arc::new(SlowTransport::default());
+        let gb = FeatureRepository {
+            client_key: Some("key_for_cancelled_polling".to_string()),
+            transport: Some(transport.clone()),
+            ..Default::default()
+        };
+        let handle = gb.start_polling(Duration::from_secs(60));
+        sleep(Duration::from_millis(50)).await;
+        assert!(gb.refreshing.load(Ordering::SeqCst));
+        drop(handle);
+        sleep(Duration::from_millis(300)).await;
+
+        assert_eq!(transport.requests.load(Ordering::SeqCst), 1);
+        assert!(!gb.refreshing.load(Ordering::SeqCst));
+        assert_eq!(gb.features.read().unwrap().len(), 0);
+        assert_eq!(*gb.refreshed_at.read().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_feature_diff() {
+        let old: FeatureMap =
+            serde_json::from_str(r#"{"kept": {"defaultValue": 1}, "changed": {"defaultValue": 1}, "dropped": {"defaultValue": 1}}"#).unwrap();
+        let new: FeatureMap =
+            serde_json::from_str(r#"{"kept": {"defaultValue": 1}, "changed": {"defaultValue": 2}, "b_new": {"defaultValue": 1}, "a_new": {}}"#)
+                .unwrap();
+        assert_eq!(
+            FeatureDiff::between(&old, &new),
+            FeatureDiff {
+                added: vec!["a_new".to_string(), "b_new".to_string()],
+                removed: vec!["dropped".to_string()],
+                modified: vec!["changed".to_string()],
+            }
+        );
+        assert!(FeatureDiff::between(&new, &new).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_diff_callback_skipped_when_nothing_changed() {
+        let diffs = Arc::new(RwLock::new(vec![]));
+        let callback_diffs = diffs.clone();
+        let mut mock_server = mockito::Server::new_async().await;
+        mock_server
+            .mock("GET", "/api/features/key_for_diffs")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"features": {"greeting": {"defaultValue": "hello"}}}"#)
+            .expect(1)
+            .create();
+        mock_server
+            .mock("GET", "/api/features/key_for_diffs")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"features": {"greeting": {"defaultValue": "hi"}, "dark_mode": {"defaultValue": false}}}"#)
+            .expect(2)
+            .create();
+
+        let mut gb = FeatureRepository {
+            api_host: mock_server.url(),
+            client_key: Some("key_for_diffs".to_string()),
+            ..Default::default()
+        };
+        gb.add_diff_callback(FeatureDiffCallback(Box::new(move |features, diff| {
+            assert!(diff.added.iter().chain(diff.modified.iter()).all(|key| features.contains_key(key)));
+            callback_diffs.write().unwrap().push(diff.clone());
+        })));
+        gb.refresh().await.unwrap();
+        gb.refresh().await.unwrap();
+        gb.refresh().await.unwrap();
+
+        assert_eq!(
+            *diffs.read().unwrap(),
+            vec![
+                FeatureDiff {
+                    added: vec!["greeting".to_string()],
+                    ..Default::default()
+                },
+                FeatureDiff {
+                    added: vec!["dark_mode".to_string()],
+                    modified: vec!["greeting".to_string()],
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remote_eval() {
+        let mut mock_server = mockito::Server::new_async().await;
+        let france = mock_server
+            .mock("POST", "/api/eval/key_for_remote_eval")
+            .match_header("content-type", "application/json")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "attributes": {"id": "user-1", "country": "france"},
+                "forcedVariations": {"checkout": 1},
+            })))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"features": {"banner_text": {"defaultValue": "Bienvenue au Beignets Acme !"}}}"#)
+            .expect(1)
+            .create();
+        let spain = mock_server
+            .mock("POST", "/api/eval/key_for_remote_eval")
+            .match_body(mockito::Matcher::PartialJson(json!({"attributes": {"country": "spain"}})))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"features": {"banner_text": {"defaultValue": "¡Bienvenidos y bienvenidas a Donas Acme!"}}}"#)
+            .expect(1)
+            .create();
+
+        let gb = FeatureRepository {
+            api_host: mock_server.url(),
+            client_key: Some("key_for_remote_eval".to_string()),
+            ..Default::default()
+        };
+        let context = |country: &str| Context {
+            attributes: json!({"id": "user-1", "country": country}),
+            forced_variations: [("checkout".to_string(), 1)].into_iter().collect(),
+            ..Default::default()
+        };
+
+        let features = gb.remote_eval(&context("france")).await.unwrap();
+        let growthbook = crate::growthbook::GrowthBook {
+            context: Context {
+                features,
+                ..context("france")
+            },
+            ..Default::default()
+        };
+        assert_eq!(growthbook.get_feature_value_as_str("banner_text", "???"), "Bienvenue au Beignets Acme !");
+
+        // same attributes are served from the cache
+        assert_eq!(gb.remote_eval(&context("france")).await.unwrap().len(), 1);
+        assert_eq!(
+            gb.get_remote_features(&context("spain")).await["banner_text"].default_value,
+            Some(json!("¡Bienvenidos y bienvenidas a Donas Acme!"))
+        );
+        france.assert_async().await;
+        spain.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_remote_eval_cache_cleared_when_features_change() {
+        let mut mock_server = mockito::Server::new_async().await;
+        let eval = mock_server
+            .mock("POST", "/api/eval/key_for_remote_eval")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"features": {"greeting": {"defaultValue": "hello"}}}"#)
+            .expect(2)
+            .create();
+        mock_server
+            .mock("GET", "/api/features/key_for_remote_eval")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(mock_features::UNENCRYPTED_FEATURES)
+            .create();
+
+        let mut gb = FeatureRepository {
+            api_host: mock_server.url(),
+            client_key: Some("key_for_remote_eval".to_string()),
+            ..Default::default()
+        };
+        let context = Context {
+            attributes: json!({"id": "user-1"}),
+            ..Default::default()
         };
+        gb.remote_eval(&context).await.unwrap();
+        gb.remote_eval(&context).await.unwrap();
+        gb.refresh().await.unwrap();
+        gb.remote_eval(&context).await.unwrap();
+        eval.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_remote_eval_falls_back_to_last_result() {
+        let mut mock_server = mockito::Server::new_async().await;
+        mock_server
+            .mock("POST", "/api/eval/key_for_remote_eval")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"features": {"greeting": {"defaultValue": "hello"}}}"#)
+            .expect(1)
+            .create();
+        mock_server.mock("POST", "/api/eval/key_for_remote_eval").with_status(503).create();
+
+        let gb = FeatureRepository {
+            api_host: mock_server.url(),
+            client_key: Some("key_for_remote_eval".to_string()),
+            ttl_seconds: -1,
+            ..Default::default()
+        };
+        let context = Context {
+            attributes: json!({"id": "user-1"}),
+            ..Default::default()
+        };
+        assert_eq!(gb.get_remote_features(&context).await.len(), 1);
+        assert_eq!(gb.remote_eval(&context).await, Err(FeatureRepositoryError::Status(503)));
+        assert_eq!(gb.get_remote_features(&context).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout_is_enforced() {
+        // accepts the connection but never answers
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            if let Ok((stream, _)) = listener.accept() {
+                std::thread::sleep(Duration::from_secs(3));
+                drop(stream);
+            }
+        });
+
+        let mut gb = FeatureRepository {
+            api_host: format!("http://{}", address),
+            client_key: Some("key_for_slow_features".to_string()),
+            timeout: 1,
+            retry_policy: RetryPolicy::none(),
+            ..Default::default()
+        };
+        let started = std::time::Instant::now();
+        gb.load_features(gb.timeout).await;
+        assert!(started.elapsed() < Duration::from_secs(3));
         assert_eq!(gb.features.read().unwrap().len(), 0);
+        assert_eq!(*gb.refreshed_at.read().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_retry_policy_delay() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            jitter: 0.0,
+            ..Default::default()
+        };
+        assert_eq!(policy.delay_for_attempt(1), Duration::from_millis(100));
+        assert_eq!(policy.delay_for_attempt(2), Duration::from_millis(200));
+        assert_eq!(policy.delay_for_attempt(3), Duration::from_millis(400));
+        assert_eq!(policy.delay_for_attempt(4), Duration::from_millis(500));
+        assert_eq!(policy.delay_for_attempt(40), Duration::from_millis(500));
+
+        let policy = RetryPolicy { jitter: 0.5, ..policy };
+        for attempt in 1..10 {
+            let delay = policy.delay_for_attempt(attempt);
+            let expected = Duration::from_millis(100 * 2u64.pow(attempt - 1)).min(Duration::from_millis(500));
+            assert!(
+                delay <= expected && delay >= expected / 2,
+                "delay {:?} out of bounds for attempt {}",
+                delay,
+                attempt
+            );
+        }
+    }
+
+    #[test]
+    fn test_failure_backoff() {
+        let policy = RetryPolicy {
+            failure_backoff: Duration::from_secs(5),
+            max_failure_backoff: Duration::from_secs(30),
+            ..Default::default()
+        };
+        assert_eq!(policy.failure_backoff_for(1), Duration::from_secs(5));
+        assert_eq!(policy.failure_backoff_for(2), Duration::from_secs(10));
+        assert_eq!(policy.failure_backoff_for(3), Duration::from_secs(20));
+        assert_eq!(policy.failure_backoff_for(4), Duration::from_secs(30));
+        assert_eq!(policy.failure_backoff_for(100), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 3 "), Some(Duration::from_secs(3)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::from_secs(0)));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_retry_until_success() {
+        let mut mock_server = mockito::Server::new_async().await;
+        let failures = mock_server
+            .mock("GET", "/api/features/key_for_retries")
+            .with_status(503)
+            .expect(2)
+            .create();
+        let success = mock_server
+            .mock("GET", "/api/features/key_for_retries")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(mock_features::UNENCRYPTED_FEATURES)
+            .create();
+
+        let mut gb = FeatureRepository {
+            api_host: mock_server.url(),
+            client_key: Some("key_for_retries".to_string()),
+            retry_policy: fast_retry_policy(),
+            ..Default::default()
+        };
         gb.get_features().await;
         wait_for_refresh(&mut gb).await;
+        failures.assert_async().await;
+        success.assert_async().await;
         assert_eq!(gb.features.read().unwrap().len(), 7);
     }
 
+    #[cfg(feature = "tokio")]
     #[tokio::test]
-    async fn test_load_features_encrypted() {
-        let mut mock_server = mockito::Server::new();
+    async fn test_retry_honors_retry_after() {
+        let mut mock_server = mockito::Server::new_async().await;
         mock_server
-            .mock("GET", "/api/features/key_for_encrypted_features")
+            .mock("GET", "/api/features/key_for_retry_after")
+            .with_status(429)
+            .with_header("retry-after", "1")
+            .expect(1)
+            .create();
+        mock_server
+            .mock("GET", "/api/features/key_for_retry_after")
             .with_status(200)
             .with_header("content-type", "application/json")
-            .with_body(mock_features::ENCRYPTED_FEATURES)
+            .with_body(mock_features::UNENCRYPTED_FEATURES)
             .create();
+
         let mut gb = FeatureRepository {
             api_host: mock_server.url(),
-            client_key: Some("key_for_encrypted_features".to_string()),
-            decryption_key: Some("BhB1wORFmZLTDjbvstvS8w==".to_string()),
+            client_key: Some("key_for_retry_after".to_string()),
+            retry_policy: fast_retry_policy(),
             ..Default::default()
         };
-        assert_eq!(gb.features.read().unwrap().len(), 0);
+        let started = std::time::Instant::now();
         gb.get_features().await;
+        // the retry-after delay can outlast a single wait_for_refresh
         wait_for_refresh(&mut gb).await;
-        assert_eq!(gb.features.read().unwrap().len(), 1);
+        wait_for_refresh(&mut gb).await;
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        assert_eq!(gb.features.read().unwrap().len(), 7);
     }
 
     #[tokio::test]
-    async fn test_single_callback() {
-        static mut COUNT: u32 = 0;
-        // unsafe is fine here, just for testing
-        let callback: FeatureRefreshCallback = FeatureRefreshCallback(Box::new(move |features| unsafe {
-            assert_eq!(features.len(), 7);
-            COUNT += 1;
-        }));
-
-        let mut mock_server = mockito::Server::new();
+    async fn test_failed_refresh_keeps_last_features() {
+        let mut mock_server = mockito::Server::new_async().await;
         mock_server
-            .mock("GET", "/api/features/key_for_unencrypted_features")
+            .mock("GET", "/api/features/key_for_failures")
             .with_status(200)
             .with_header("content-type", "application/json")
             .with_body(mock_features::UNENCRYPTED_FEATURES)
+            .expect(1)
+            .create();
+        // without tokio there is no timer, so failed requests are not retried
+        let failures = mock_server
+            .mock("GET", "/api/features/key_for_failures")
+            .with_status(500)
+            .expect(if cfg!(feature = "tokio") { 3 } else { 1 })
             .create();
 
         let mut gb = FeatureRepository {
             api_host: mock_server.url(),
-            client_key: Some("key_for_unencrypted_features".to_string()),
+            client_key: Some("key_for_failures".to_string()),
+            retry_policy: fast_retry_policy(),
             ..Default::default()
         };
-        gb.add_refresh_callback(callback);
         gb.get_features().await;
         wait_for_refresh(&mut gb).await;
-        assert_eq!(unsafe { COUNT }, 1);
+        assert_eq!(gb.features.read().unwrap().len(), 7);
+
+        *gb.refreshed_at.write().unwrap() = 0;
+        gb.get_features().await;
+        wait_for_mock(&failures).await;
+        failures.assert_async().await;
+        assert_eq!(gb.features.read().unwrap().len(), 7);
+        assert_eq!(*gb.refreshed_at.read().unwrap(), 0);
+
+        // the failure backoff keeps the next reads from fetching again
+        gb.get_features().await;
+        wait_for_refresh(&mut gb).await;
+        failures.assert_async().await;
+        let next_attempt_at = gb.status().next_attempt_at.unwrap();
+        assert!(next_attempt_at > chrono::Utc::now().timestamp());
+
+        // refresh() doesn't wait for it
+        assert!(gb.refresh().await.is_err());
+        assert_eq!(gb.status().consecutive_failures, 2);
     }
 
+    #[cfg(feature = "tokio")]
     #[tokio::test]
-    async fn test_multiple_callback() {
-        static mut COUNT: u32 = 0;
-        // TODO: unsafe is fine here, just for testing. Still better way?
-        let callback_one: FeatureRefreshCallback = FeatureRefreshCallback(Box::new(move |features| unsafe {
-            assert_eq!(features.len(), 7);
-            COUNT += 1;
-        }));
-        let callback_two: FeatureRefreshCallback = FeatureRefreshCallback(Box::new(move |features| unsafe {
-            assert_eq!(features.len(), 7);
-            COUNT += 1;
-        }));
+    async fn test_retry_after_beyond_policy_defers_next_attempt() {
+        let mut mock_server = mockito::Server::new_async().await;
+        let throttled = mock_server
+            .mock("GET", "/api/features/key_for_long_retry_after")
+            .with_status(503)
+            .with_header("retry-after", "600")
+            .expect(1)
+            .create();
+
+        let mut gb = FeatureRepository {
+            api_host: mock_server.url(),
+            client_key: Some("key_for_long_retry_after".to_string()),
+            retry_policy: fast_retry_policy(),
+            ..Default::default()
+        };
+        gb.get_features().await;
+        wait_for_mock(&throttled).await;
+        wait_for_refresh(&mut gb).await;
+        gb.get_features().await;
+        wait_for_refresh(&mut gb).await;
+        throttled.assert_async().await;
+        let next_attempt_at = gb.status().next_attempt_at.unwrap();
+        assert!(next_attempt_at >= chrono::Utc::now().timestamp() + 590);
+    }
 
-        let mut mock_server = mockito::Server::new();
+    #[tokio::test]
+    async fn test_refresh_outcomes() {
+        let mut mock_server = mockito::Server::new_async().await;
         mock_server
-            .mock("GET", "/api/features/key_for_unencrypted_features")
+            .mock("GET", "/api/features/key_for_refresh")
+            .match_header("if-none-match", mockito::Matcher::Missing)
             .with_status(200)
             .with_header("content-type", "application/json")
             .with_body(mock_features::UNENCRYPTED_FEATURES)
+            .expect(2)
             .create();
 
         let mut gb = FeatureRepository {
             api_host: mock_server.url(),
-            client_key: Some("key_for_unencrypted_features".to_string()),
+            client_key: Some("key_for_refresh".to_string()),
             ..Default::default()
         };
-        gb.add_refresh_callback(callback_one);
-        gb.add_refresh_callback(callback_two);
-        gb.get_features().await;
-        wait_for_refresh(&mut gb).await;
-        assert_eq!(unsafe { COUNT }, 2);
+        assert_eq!(gb.refresh().await, Ok(RefreshOutcome::Updated));
+        assert_eq!(gb.refresh().await, Ok(RefreshOutcome::Unchanged));
     }
 
     #[tokio::test]
-    async fn test_clear_callback() {
-        static mut COUNT: u32 = 0;
-        // TODO: unsafe is fine here, just for testing. Still better way?
-        let callback: FeatureRefreshCallback = FeatureRefreshCallback(Box::new(move |features| unsafe {
-            assert_eq!(features.len(), 1);
-            COUNT += 1;
-        }));
+    async fn test_outdated_payload_is_rejected() {
+        let mut mock_server = mockito::Server::new_async().await;
+        mock_server
+            .mock("GET", "/api/features/key_for_outdated")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_header("etag", "\"newer\"")
+            .with_body(r#"{"features": {"greeting": {"defaultValue": "hi"}}, "dateUpdated": "2023-08-03T10:00:00.000Z"}"#)
+            .expect(1)
+            .create();
+        mock_server
+            .mock("GET", "/api/features/key_for_outdated")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_header("etag", "\"older\"")
+            .with_body(r#"{"features": {"greeting": {"defaultValue": "hello"}}, "dateUpdated": "2023-08-02T19:11:46.550Z"}"#)
+            .create();
 
-        let mut mock_server = mockito::Server::new();
+        let cache = InMemoryFeatureCache::default();
+        let mut gb = FeatureRepository {
+            api_host: mock_server.url(),
+            client_key: Some("key_for_outdated".to_string()),
+            cache: Some(Arc::new(cache.clone())),
+            ..Default::default()
+        };
+        assert_eq!(gb.refresh().await, Ok(RefreshOutcome::Updated));
+        assert_eq!(gb.refresh().await, Ok(RefreshOutcome::Outdated));
+
+        assert_eq!(gb.features.read().unwrap()["greeting"].default_value, Some(json!("hi")));
+        assert_eq!(gb.status().date_updated, Some("2023-08-03T10:00:00.000Z".to_string()));
+        assert_eq!(*gb.etag.read().unwrap(), Some("\"newer\"".to_string()));
+        let cached = cache.get("key_for_outdated").await.unwrap().unwrap();
+        assert_eq!(cached.payload["dateUpdated"], json!("2023-08-03T10:00:00.000Z"));
+    }
+
+    #[test]
+    fn test_payloads_without_date_updated_are_applied() {
+        let gb = FeatureRepository::default();
+        let newer = json!({"features": {"greeting": {"defaultValue": "hi"}}, "dateUpdated": "2023-08-03T10:00:00.000Z"});
+        assert_eq!(gb.bootstrap(&newer), Ok(RefreshOutcome::Updated));
+        assert_eq!(
+            gb.bootstrap(&json!({"features": {"greeting": {"defaultValue": "hello"}}})),
+            Ok(RefreshOutcome::Updated)
+        );
+        assert_eq!(gb.bootstrap(&newer), Ok(RefreshOutcome::Updated));
+        assert_eq!(
+            gb.bootstrap(&json!({"features": {}, "dateUpdated": "2023-08-03T09:59:59.999Z"})),
+            Ok(RefreshOutcome::Outdated)
+        );
+        assert_eq!(gb.features.read().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_concurrent_payloads_never_roll_back() {
+        let gb = FeatureRepository::default();
+        // reading the payload from a callback must not deadlock with a concurrent apply
+        let payload = gb.payload.clone();
+        let mut callback_gb = gb.clone();
+        callback_gb.add_refresh_callback(FeatureRefreshCallback(Box::new(move |_features| {
+            assert!(payload.read().unwrap().is_some());
+        })));
+
+        let appliers: Vec<_> = (0..8)
+            .map(|thread| {
+                let gb = gb.clone();
+                std::thread::spawn(move || {
+                    for minute in (0..40).filter(|minute| minute % 8 == thread) {
+                        let payload = json!({
+                            "features": {"version": {"defaultValue": minute}},
+                            "dateUpdated": format!("2023-08-03T10:{:02}:00.000Z", minute),
+                        });
+                        gb.bootstrap(&payload).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for applier in appliers {
+            applier.join().unwrap();
+        }
+
+        assert_eq!(gb.features.read().unwrap()["version"].default_value, Some(json!(39)));
+        assert_eq!(gb.status().date_updated, Some("2023-08-03T10:39:00.000Z".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_errors() {
+        let mut mock_server = mockito::Server::new_async().await;
+        mock_server
+            .mock("GET", "/api/features/key_for_missing_features")
+            .with_status(404)
+            .create();
+        mock_server
+            .mock("GET", "/api/features/key_for_broken_payload")
+            .with_status(200)
+            .with_body("{\"features\": ")
+            .create();
         mock_server
             .mock("GET", "/api/features/key_for_encrypted_features")
             .with_status(200)
@@ -462,24 +2268,299 @@ mod tests {
             .with_body(mock_features::ENCRYPTED_FEATURES)
             .create();
 
+        let repository = |client_key: Option<&str>, decryption_key: Option<&str>| FeatureRepository {
+            api_host: mock_server.url(),
+            client_key: client_key.map(str::to_string),
+            decryption_key: Arc::new(RwLock::new(decryption_key.map(str::to_string))),
+            retry_policy: RetryPolicy::none(),
+            ..Default::default()
+        };
+        assert_eq!(repository(None, None).refresh().await, Err(FeatureRepositoryError::MissingClientKey));
+        assert_eq!(
+            repository(Some("key_for_missing_features"), None).refresh().await,
+            Err(FeatureRepositoryError::Status(404))
+        );
+        assert!(matches!(
+            repository(Some("key_for_broken_payload"), None).refresh().await,
+            Err(FeatureRepositoryError::InvalidPayload(_))
+        ));
+        assert_eq!(
+            repository(Some("key_for_encrypted_features"), None).refresh().await,
+            Err(FeatureRepositoryError::MissingDecryptionKey)
+        );
+        assert_eq!(
+            repository(Some("key_for_encrypted_features"), Some("bm90IHRoZSByaWdodCBrZXk="))
+                .refresh()
+                .await,
+            Err(FeatureRepositoryError::Decryption)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_callback() {
+        let errors = Arc::new(RwLock::new(vec![]));
+        let callback_errors = errors.clone();
+        let mut mock_server = mockito::Server::new_async().await;
+        mock_server.mock("GET", "/api/features/key_for_failures").with_status(500).create();
+
         let mut gb = FeatureRepository {
             api_host: mock_server.url(),
-            client_key: Some("key_for_encrypted_features".to_string()),
-            decryption_key: Some("BhB1wORFmZLTDjbvstvS8w==".to_string()),
+            client_key: Some("key_for_failures".to_string()),
+            retry_policy: RetryPolicy::none(),
             ..Default::default()
         };
-        gb.add_refresh_callback(callback);
+        gb.add_error_callback(FeatureRepositoryErrorCallback(Box::new(move |e| {
+            callback_errors.write().unwrap().push(e.clone());
+        })));
         gb.get_features().await;
-        wait_for_refresh(&mut gb).await;
-        assert_eq!(unsafe { COUNT }, 1);
-
-        unsafe {
-            COUNT = 0;
+        let mut timeout = 1000;
+        while errors.read().unwrap().is_empty() && timeout > 0 {
+            sleep(Duration::from_millis(10)).await;
+            timeout -= 10;
         }
-        *gb.refreshed_at.write().unwrap() = 0;
-        gb.clear_refresh_callbacks();
+        assert_eq!(*errors.read().unwrap(), vec![FeatureRepositoryError::Status(500)]);
+    }
+
+    #[tokio::test]
+    async fn test_status_tracks_failures_and_recovery() {
+        let mut mock_server = mockito::Server::new_async().await;
+        mock_server
+            .mock("GET", "/api/features/key_for_status")
+            .with_status(500)
+            .expect(2)
+            .create();
+        mock_server
+            .mock("GET", "/api/features/key_for_status")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(mock_features::UNENCRYPTED_FEATURES)
+            .create();
+
+        let mut gb = FeatureRepository {
+            api_host: mock_server.url(),
+            client_key: Some("key_for_status".to_string()),
+            retry_policy: RetryPolicy::none(),
+            ..Default::default()
+        };
+        assert_eq!(gb.status(), RepositoryStatus::default());
+
+        assert!(gb.refresh().await.is_err());
+        assert!(gb.refresh().await.is_err());
+        let status = gb.status();
+        assert!(status.last_attempt_at.is_some());
+        assert_eq!(status.last_success_at, None);
+        assert_eq!(status.last_error, Some(FeatureRepositoryError::Status(500)));
+        assert_eq!(status.consecutive_failures, 2);
+        assert_eq!(status.source, None);
+
+        gb.refresh().await.unwrap();
+        let status = gb.status();
+        assert!(status.last_success_at.is_some());
+        assert_eq!(status.last_error, None);
+        assert_eq!(status.consecutive_failures, 0);
+        assert_eq!(status.date_updated, Some("2023-08-02T19:11:46.550Z".to_string()));
+        assert_eq!(status.feature_count, 7);
+        assert_eq!(status.source, Some(PayloadSource::Network));
+    }
+
+    #[test]
+    fn test_status_after_bootstrap() {
+        let gb = FeatureRepository::default();
+        let payload: Value = serde_json::from_str(mock_features::UNENCRYPTED_FEATURES).unwrap();
+        assert_eq!(gb.bootstrap(&payload), Ok(RefreshOutcome::Updated));
+        let status = gb.status();
+        assert_eq!(status.source, Some(PayloadSource::Bootstrap));
+        assert_eq!(status.feature_count, 7);
+        assert_eq!(status.last_success_at, None);
+        assert!(gb.is_cache_expired());
+    }
+
+    #[test]
+    fn test_feature_snapshots_are_shared_until_replaced() {
+        let gb = FeatureRepository::default();
+        gb.bootstrap(&json!({"features": {"greeting": {"defaultValue": "hello"}}})).unwrap();
+        let first = gb.current_features();
+        assert!(Arc::ptr_eq(&first, &gb.current_features()));
+
+        // an unchanged payload keeps the snapshot, a changed one swaps it and leaves the old one intact
+        gb.bootstrap(&json!({"features": {"greeting": {"defaultValue": "hello"}}})).unwrap();
+        assert!(Arc::ptr_eq(&first, &gb.current_features()));
+        gb.bootstrap(&json!({"features": {"greeting": {"defaultValue": "hi"}}})).unwrap();
+        let second = gb.current_features();
+        assert!(!Arc::ptr_eq(&first, &second));
+        assert_eq!(first["greeting"].default_value, Some(json!("hello")));
+        assert_eq!(second["greeting"].default_value, Some(json!("hi")));
+
+        let context = Context {
+            features: second.clone(),
+            ..Default::default()
+        };
+        assert!(Arc::ptr_eq(&context.features, &second));
+    }
+
+    #[test]
+    fn test_saved_groups_are_stored() {
+        let refreshes = Arc::new(AtomicU32::new(0));
+        let callback_refreshes = refreshes.clone();
+        let mut gb = FeatureRepository::default();
+        gb.add_refresh_callback(FeatureRefreshCallback(Box::new(move |_features| {
+            callback_refreshes.fetch_add(1, Ordering::SeqCst);
+        })));
+
+        let payload = json!({"features": {"greeting": {"defaultValue": "hello"}}, "savedGroups": {"beta_testers": ["user-1"]}});
+        assert_eq!(gb.bootstrap(&payload), Ok(RefreshOutcome::Updated));
+        assert_eq!(gb.get_saved_groups().get("beta_testers"), Some(&vec![json!("user-1")]));
+
+        // only the saved groups changed, still an update
+        let payload = json!({"features": {"greeting": {"defaultValue": "hello"}}, "savedGroups": {"beta_testers": ["user-1", "user-2"]}});
+        assert_eq!(gb.bootstrap(&payload), Ok(RefreshOutcome::Updated));
+        assert_eq!(gb.bootstrap(&payload), Ok(RefreshOutcome::Unchanged));
+        assert_eq!(gb.get_saved_groups().get("beta_testers").map(Vec::len), Some(2));
+        assert_eq!(refreshes.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_experiments_are_stored() {
+        let gb = FeatureRepository::default();
+        let payload = json!({
+            "features": {},
+            "experiments": [{
+                "key": "pricing-redirect",
+                "urlPatterns": [{"type": "regex", "include": true, "pattern": "^/pricing"}],
+                "variations": [{}, {"urlRedirect": "/pricing-v2"}]
+            }]
+        });
+        assert_eq!(gb.bootstrap(&payload), Ok(RefreshOutcome::Updated));
+        let experiments = gb.get_experiments();
+        assert_eq!(experiments.len(), 1);
+        assert_eq!(experiments[0].url_patterns[0].target_type, UrlTargetType::Regex);
+        assert_eq!(experiments[0].changes()[1].url_redirect, Some("/pricing-v2".to_string()));
+
+        assert_eq!(gb.bootstrap(&json!({"features": {}})), Ok(RefreshOutcome::Updated));
+        assert!(gb.get_experiments().is_empty());
+    }
+
+    #[test]
+    fn test_encrypted_saved_groups() {
+        let payload = json!({
+            "features": {},
+            "encryptedFeatures": "UqANSnJ7xTTK9y2PALtnwQ==.BZAstXrI9eh9qlvp7VinD8CKk9ZE8755vnFtkClJNYstTUwF4FKwWWq84F/DFTe+2Xlzbys83S1Ih6XIFhoigKIQeImlnzR3GJ6Bvj3REbKccw9TJz4bX3ozFzSNBbZbLAynnd9aTLK0PAYASLXKtIaAs/K0WSbV7mM95CVMt9DU7w1TKme/tQcqfEn+CJhi2WHNdEzGs18j9t7zXcRgdAvXizLzP7HdOnCmfXy9bZbpqWmAdUBZ0yhmb2PGXa5FBwet7h1MV0kRFX++WocwjA==",
+            "encryptedSavedGroups": "6hQnNgR865Jg+gIShlwZ5g==.SgWmVnMJ/s+LfIvJmi+z0HyduJkOtAeb0EZ6wR4W5oppkkfUhQs2hxNDUQkNN4M3"
+        });
+        let gb = FeatureRepository {
+            decryption_key: Arc::new(RwLock::new(Some("BhB1wORFmZLTDjbvstvS8w==".to_string()))),
+            ..Default::default()
+        };
+        assert_eq!(gb.bootstrap(&payload), Ok(RefreshOutcome::Updated));
+        assert_eq!(gb.get_saved_groups().get("beta_testers"), Some(&vec![json!("user-1"), json!("user-2")]));
+
+        let payload = json!({"features": {}, "encryptedSavedGroups": "6hQnNgR865Jg+gIShlwZ5g==.SgWmVnMJ/s+LfIvJmi+z0HyduJkOtAeb0EZ6wR4W5oppkkfUhQs2hxNDUQkNN4M3"});
+        assert_eq!(
+            FeatureRepository::default().bootstrap(&payload),
+            Err(FeatureRepositoryError::MissingDecryptionKey)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_are_not_retried() {
+        let mut mock_server = mockito::Server::new_async().await;
+        let not_found = mock_server
+            .mock("GET", "/api/features/key_for_missing_features")
+            .with_status(404)
+            .expect(1)
+            .create();
+
+        let mut gb = FeatureRepository {
+            api_host: mock_server.url(),
+            client_key: Some("key_for_missing_features".to_string()),
+            retry_policy: fast_retry_policy(),
+            ..Default::default()
+        };
         gb.get_features().await;
+        wait_for_mock(&not_found).await;
+        sleep(Duration::from_millis(100)).await;
+        not_found.assert_async().await;
+        assert_eq!(gb.features.read().unwrap().len(), 0);
+    }
+
+    #[cfg(all(feature = "tokio", feature = "reqwest"))]
+    #[tokio::test]
+    async fn test_streaming_applies_features_event() {
+        let refreshes = Arc::new(AtomicU32::new(0));
+        let callback_refreshes = refreshes.clone();
+        let callback: FeatureRefreshCallback = FeatureRefreshCallback(Box::new(move |features| {
+            assert_eq!(features.len(), 7);
+            callback_refreshes.fetch_add(1, Ordering::SeqCst);
+        }));
+
+        let mut mock_server = mockito::Server::new_async().await;
+        let payload: Value = serde_json::from_str(mock_features::UNENCRYPTED_FEATURES).unwrap();
+        mock_server
+            .mock("GET", "/sub/key_for_streaming")
+            .match_header("accept", "text/event-stream")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(format!(": connected\n\nevent: features\ndata: {}\n\n", payload))
+            .create();
+
+        let mut gb = FeatureRepository {
+            api_host: mock_server.url(),
+            client_key: Some("key_for_streaming".to_string()),
+            ..Default::default()
+        };
+        gb.add_refresh_callback(callback);
+        let handle = gb.start_streaming();
         wait_for_refresh(&mut gb).await;
-        assert_eq!(unsafe { COUNT }, 0);
+        assert_eq!(gb.features.read().unwrap().len(), 7);
+        assert_eq!(refreshes.load(Ordering::SeqCst), 1);
+        handle.stop();
+    }
+
+    #[cfg(all(feature = "tokio", feature = "reqwest"))]
+    #[tokio::test]
+    async fn test_streaming_applies_encrypted_features_updated_event() {
+        let mut mock_server = mockito::Server::new_async().await;
+        let payload: Value = serde_json::from_str(mock_features::ENCRYPTED_FEATURES).unwrap();
+        mock_server
+            .mock("GET", "/sub/key_for_encrypted_streaming")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(format!("event: features-updated\r\ndata: {}\r\n\r\n", payload))
+            .create();
+
+        let mut gb = FeatureRepository {
+            api_host: mock_server.url(),
+            client_key: Some("key_for_encrypted_streaming".to_string()),
+            decryption_key: Arc::new(RwLock::new(Some("BhB1wORFmZLTDjbvstvS8w==".to_string()))),
+            ..Default::default()
+        };
+        let _handle = gb.start_streaming();
+        wait_for_refresh(&mut gb).await;
+        assert_eq!(gb.features.read().unwrap().len(), 1);
+    }
+
+    #[cfg(all(feature = "tokio", feature = "reqwest"))]
+    #[tokio::test]
+    async fn test_streaming_reconnects_after_stream_closes() {
+        let mut mock_server = mockito::Server::new_async().await;
+        let mock = mock_server
+            .mock("GET", "/sub/key_for_reconnect")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body("event: features\ndata: {\"features\": {\"greeting\": {\"defaultValue\": \"hi\"}}}\n\n")
+            .expect_at_least(2)
+            .create();
+
+        let gb = FeatureRepository {
+            api_host: mock_server.url(),
+            client_key: Some("key_for_reconnect".to_string()),
+            ..Default::default()
+        };
+        let handle = gb.start_streaming();
+        sleep(STREAMING_MIN_BACKOFF + Duration::from_millis(500)).await;
+        assert!(handle.is_running());
+        handle.stop();
+        mock.assert_async().await;
+        assert_eq!(gb.features.read().unwrap().len(), 1);
     }
 }
@@ -0,0 +1,124 @@
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SseEvent {
+    pub event: String,
+    pub data: String,
+    pub id: Option<String>,
+}
+
+// incremental parser for text/event-stream bodies, fed with raw chunks as they arrive
+#[derive(Debug, Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    event: Option<String>,
+    data: Vec<String>,
+    id: Option<String>,
+}
+
+impl SseParser {
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        // chunks can end inside a multi-byte character, so only complete lines are decoded
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = vec![];
+        while let Some(position) = self.buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=position).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.is_empty() {
+                if let Some(event) = self.dispatch() {
+                    events.push(event);
+                }
+                continue;
+            }
+            if line.starts_with(':') {
+                continue;
+            }
+            let (field, value) = match line.split_once(':') {
+                Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+                None => (line, ""),
+            };
+            match field {
+                "event" => self.event = Some(value.to_string()),
+                "data" => self.data.push(value.to_string()),
+                "id" => self.id = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        events
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        let id = self.id.take();
+        if self.data.is_empty() {
+            return None;
+        }
+        let data = self.data.join("\n");
+        self.data.clear();
+        Some(SseEvent {
+            event: event.unwrap_or_else(|| "message".to_string()),
+            data,
+            id,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sse::{SseEvent, SseParser};
+
+    #[test]
+    fn test_parse_single_event() {
+        let mut parser = SseParser::default();
+        let events = parser.push(b"event: features\ndata: {\"features\": {}}\n\n");
+        assert_eq!(
+            events,
+            vec![SseEvent {
+                event: "features".to_string(),
+                data: "{\"features\": {}}".to_string(),
+                id: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_event_split_across_chunks() {
+        let mut parser = SseParser::default();
+        assert!(parser.push(b"event: features-upd").is_empty());
+        assert!(parser.push(b"ated\r\ndata: {\"a\":").is_empty());
+        assert!(parser.push(b" 1}\r\nid: 7\r\n").is_empty());
+        let events = parser.push(b"\r\n");
+        assert_eq!(
+            events,
+            vec![SseEvent {
+                event: "features-updated".to_string(),
+                data: "{\"a\": 1}".to_string(),
+                id: Some("7".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_multi_byte_character_split_across_chunks() {
+        let mut parser = SseParser::default();
+        let payload = "data: {\"greeting\": \"¡Bienvenidos\"}\n\n".as_bytes();
+        let split = payload.iter().position(|byte| *byte == 0xC2).unwrap() + 1;
+        assert!(parser.push(&payload[..split]).is_empty());
+        let events = parser.push(&payload[split..]);
+        assert_eq!(events[0].data, "{\"greeting\": \"¡Bienvenidos\"}");
+    }
+
+    #[test]
+    fn test_parse_ignores_comments_and_joins_data_lines() {
+        let mut parser = SseParser::default();
+        let events = parser.push(b": keep-alive\n\ndata: first\ndata: second\n\n");
+        assert_eq!(
+            events,
+            vec![SseEvent {
+                event: "message".to_string(),
+                data: "first\nsecond".to_string(),
+                id: None,
+            }]
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Debug, Display};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cache::{encode_file_name, write_atomically};
+
+// the variations one user (attribute name and value) was bucketed into,
+// keyed by `{experimentKey}__{bucketVersion}` with the variation key as value
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct StickyAssignmentsDocument {
+    pub attribute_name: String,
+    pub attribute_value: String,
+    pub assignments: HashMap<String, String>,
+}
+
+impl StickyAssignmentsDocument {
+    pub fn new(attribute_name: &str, attribute_value: &str) -> Self {
+        StickyAssignmentsDocument {
+            attribute_name: attribute_name.to_string(),
+            attribute_value: attribute_value.to_string(),
+            assignments: HashMap::new(),
+        }
+    }
+
+    pub fn key(&self) -> String {
+        sticky_attribute_key(&self.attribute_name, &self.attribute_value)
+    }
+}
+
+pub fn sticky_attribute_key(attribute_name: &str, attribute_value: &str) -> String {
+    format!("{}||{}", attribute_name, attribute_value)
+}
+
+pub fn sticky_experiment_key(experiment_key: &str, bucket_version: i32) -> String {
+    format!("{}__{}", experiment_key, bucket_version)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StickyBucketError {
+    pub message: String,
+}
+
+impl Display for StickyBucketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for StickyBucketError {}
+
+impl From<io::Error> for StickyBucketError {
+    fn from(e: io::Error) -> Self {
+        StickyBucketError { message: e.to_string() }
+    }
+}
+
+// GrowthBook reads the assignments before bucketing a user and saves new ones right after.
+// evaluation is synchronous, so is this; implement it on top of a database to keep users in their variation
+// across instances and when weights change
+pub trait StickyBucketService: Debug + Send + Sync {
+    fn get_assignments(&self, attribute_name: &str, attribute_value: &str) -> Result<Option<StickyAssignmentsDocument>, StickyBucketError>;
+    fn save_assignments(&self, document: &StickyAssignmentsDocument) -> Result<(), StickyBucketError>;
+}
+
+// clones share the same documents, so one instance can back every GrowthBook built in a process
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryStickyBucketService {
+    documents: Arc<RwLock<HashMap<String, StickyAssignmentsDocument>>>,
+}
+
+impl StickyBucketService for InMemoryStickyBucketService {
+    fn get_assignments(&self, attribute_name: &str, attribute_value: &str) -> Result<Option<StickyAssignmentsDocument>, StickyBucketError> {
+        match self.documents.read() {
+            Ok(documents) => Ok(documents.get(&sticky_attribute_key(attribute_name, attribute_value)).cloned()),
+            Err(_) => Err(StickyBucketError {
+                message: "sticky bucket lock poisoned".to_string(),
+            }),
+        }
+    }
+
+    fn save_assignments(&self, document: &StickyAssignmentsDocument) -> Result<(), StickyBucketError> {
+        match self.documents.write() {
+            Ok(mut documents) => {
+                documents.insert(document.key(), document.clone());
+                Ok(())
+            }
+            Err(_) => Err(StickyBucketError {
+                message: "sticky bucket lock poisoned".to_string(),
+            }),
+        }
+    }
+}
+
+// one json file per attribute name and value in `directory`, survives restarts
+#[derive(Debug, Clone)]
+pub struct FileStickyBucketService {
+    pub directory: PathBuf,
+}
+
+impl FileStickyBucketService {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        FileStickyBucketService { directory: directory.into() }
+    }
+
+    pub fn path_for(&self, attribute_name: &str, attribute_value: &str) -> PathBuf {
+        let file_name = encode_file_name(&sticky_attribute_key(attribute_name, attribute_value));
+        self.directory.join(format!("growthbook-sticky-{}.json", file_name))
+    }
+}
+
+impl StickyBucketService for FileStickyBucketService {
+    fn get_assignments(&self, attribute_name: &str, attribute_value: &str) -> Result<Option<StickyAssignmentsDocument>, StickyBucketError> {
+        let content = match fs::read_to_string(self.path_for(attribute_name, attribute_value)) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let document: StickyAssignmentsDocument = serde_json::from_str(&content).map_err(|e| StickyBucketError {
+            message: format!("invalid sticky bucket file: {}", e),
+        })?;
+        Ok(Some(document))
+    }
+
+    fn save_assignments(&self, document: &StickyAssignmentsDocument) -> Result<(), StickyBucketError> {
+        let content = serde_json::to_vec(document).map_err(|e| StickyBucketError { message: e.to_string() })?;
+        let path = self.path_for(&document.attribute_name, &document.attribute_value);
+        Ok(write_atomically(&path, &content)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::sticky_bucket::{FileStickyBucketService, InMemoryStickyBucketService, StickyAssignmentsDocument, StickyBucketService};
+
+    fn document(attribute_value: &str) -> StickyAssignmentsDocument {
+        let mut document = StickyAssignmentsDocument::new("id", attribute_value);
+        document.assignments.insert("checkout__0".to_string(), "control".to_string());
+        document
+    }
+
+    #[test]
+    fn test_file_service_save_and_get() {
+        let directory = std::env::temp_dir().join(format!("growthbook-sticky-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let service = FileStickyBucketService::new(&directory);
+
+        assert_eq!(service.get_assignments("id", "user/1"), Ok(None));
+        service.save_assignments(&document("user/1")).unwrap();
+        assert_eq!(service.get_assignments("id", "user/1"), Ok(Some(document("user/1"))));
+
+        // users whose values only differ in characters a file name can't hold keep separate files
+        assert_ne!(service.path_for("id", "user/1"), service.path_for("id", "user_1"));
+        assert_eq!(service.get_assignments("id", "user_1"), Ok(None));
+        service.save_assignments(&document("user_1")).unwrap();
+        assert_eq!(service.get_assignments("id", "user/1"), Ok(Some(document("user/1"))));
+
+        // nor do values that only differ in case, which case-insensitive file systems would fold together
+        let upper = service.path_for("id", "Alice").file_name().unwrap().to_string_lossy().to_lowercase();
+        let lower = service.path_for("id", "alice").file_name().unwrap().to_string_lossy().to_lowercase();
+        assert_ne!(upper, lower);
+        service.save_assignments(&document("Alice")).unwrap();
+        assert_eq!(service.get_assignments("id", "alice"), Ok(None));
+        service.save_assignments(&document("alice")).unwrap();
+        assert_eq!(service.get_assignments("id", "Alice"), Ok(Some(document("Alice"))));
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_in_memory_service_is_shared_between_clones() {
+        let service = InMemoryStickyBucketService::default();
+        let clone = service.clone();
+        service.save_assignments(&document("1")).unwrap();
+        assert_eq!(clone.get_assignments("id", "1"), Ok(Some(document("1"))));
+        assert_eq!(clone.get_assignments("id", "2"), Ok(None));
+    }
+}
@@ -1,11 +1,14 @@
 use std::convert::TryInto;
 use std::str;
+use std::sync::OnceLock;
 
 use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
 use data_encoding::BASE64;
-use url::Url;
+use regex::Regex;
+use serde_json::Value;
+use url::{Position, Url};
 
-use crate::model::{BucketRange, Namespace};
+use crate::model::{BucketRange, Namespace, UrlTarget, UrlTargetType};
 
 const INIT32: u32 = 0x811c9dc5;
 const PRIME32: u32 = 0x01000193;
@@ -39,6 +42,53 @@ pub fn in_namespace(user_id: &str, namespace: &Namespace) -> bool {
     (hash >= namespace.range_start) && (hash < namespace.range_end)
 }
 
+// the string an attribute value is hashed as, shared by experiments, rollouts, filters and namespaces.
+// numbers are written the way javascript's String(n) does (`1.0` becomes "1", `1e-7` stays "1e-7", integers
+// beyond 2^53 round to the nearest double), so users land in the same bucket as in the other sdks. like there, falsy values (`0`, `false`, `""`) count as missing,
+// and null, arrays and objects can't be hashed. all of those come back empty
+pub fn hash_value_string(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        Value::Bool(true) => "true".to_string(),
+        Value::Number(number) => match number.as_f64() {
+            Some(0.0) | None => String::new(),
+            Some(number) => js_number_string(number),
+        },
+        _ => String::new(),
+    }
+}
+
+// Number::toString from the ecmascript spec, on top of the shortest round-trip digits rust already finds
+fn js_number_string(number: f64) -> String {
+    if number == 0.0 {
+        return "0".to_string();
+    }
+    let scientific = format!("{:e}", number.abs());
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let digits = mantissa.replace('.', "");
+    let k = digits.len() as i32;
+    // the decimal point goes after n digits
+    let n = exponent.parse::<i32>().unwrap_or(0) + 1;
+    let formatted = if k <= n && n <= 21 {
+        format!("{}{}", digits, "0".repeat((n - k) as usize))
+    } else if 0 < n && n <= 21 {
+        format!("{}.{}", &digits[..n as usize], &digits[n as usize..])
+    } else if -6 < n && n <= 0 {
+        format!("0.{}{}", "0".repeat(-n as usize), digits)
+    } else {
+        let sign = if n > 0 { "+" } else { "-" };
+        match digits.split_at(1) {
+            (first, "") => format!("{}e{}{}", first, sign, (n - 1).abs()),
+            (first, rest) => format!("{}.{}e{}{}", first, rest, sign, (n - 1).abs()),
+        }
+    };
+    if number < 0.0 {
+        format!("-{}", formatted)
+    } else {
+        formatted
+    }
+}
+
 pub fn get_equal_weights(num_variations: i32) -> Vec<f32> {
     if num_variations < 1 {
         vec![]
@@ -88,6 +138,136 @@ pub fn get_query_string_override(id: &str, url: &str, num_variations: i32) -> Op
     None
 }
 
+// same rules as the other sdks: any matching exclude wins, otherwise one include has to match
+// when there are includes at all. relative urls are resolved against a placeholder host
+pub fn is_url_targeted(url: &str, targets: &[UrlTarget]) -> bool {
+    if targets.is_empty() {
+        return false;
+    }
+    let parsed = match Url::parse("https://_").and_then(|base| base.join(url)) {
+        Ok(parsed) => parsed,
+        Err(_) => return false,
+    };
+
+    let mut has_include_rules = false;
+    let mut is_included = false;
+    for target in targets {
+        let matched = match url_matcher(target) {
+            Some(UrlMatcher::Regex(regex)) => regex.is_match(parsed.as_str()) || regex.is_match(&parsed[Position::BeforePath..]),
+            Some(UrlMatcher::Simple(parts)) => eval_simple_url_target(&parsed, parts),
+            None => false,
+        };
+        if !target.include {
+            if matched {
+                return false;
+            }
+        } else {
+            has_include_rules = true;
+            is_included = is_included || matched;
+        }
+    }
+    is_included || !has_include_rules
+}
+
+// the regexes a url target is matched with. they are built from the pattern once, when the repository
+// applies a payload or else the first time the target is matched, and clones of the target share them
+#[derive(Debug, Clone, Default)]
+pub struct CompiledUrlTarget(OnceLock<Option<UrlMatcher>>);
+
+// built from the pattern, so it never tells two targets apart
+impl PartialEq for CompiledUrlTarget {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Clone)]
+enum UrlMatcher {
+    Regex(Regex),
+    Simple(Vec<(UrlPart, Regex)>),
+}
+
+#[derive(Debug, Clone)]
+enum UrlPart {
+    Host,
+    Path,
+    Fragment,
+    Query(String),
+}
+
+pub fn compile_url_targets(targets: &[UrlTarget]) {
+    for target in targets {
+        url_matcher(target);
+    }
+}
+
+// invalid patterns match nothing
+fn url_matcher(target: &UrlTarget) -> Option<&UrlMatcher> {
+    target
+        .compiled
+        .0
+        .get_or_init(|| match target.target_type {
+            UrlTargetType::Regex => Regex::new(&target.pattern).ok().map(UrlMatcher::Regex),
+            UrlTargetType::Simple => compile_simple_url_target(&target.pattern).map(UrlMatcher::Simple),
+        })
+        .as_ref()
+}
+
+// simple patterns compare host, path, hash and query params one by one, `*` matching anything
+fn compile_simple_url_target(pattern: &str) -> Option<Vec<(UrlPart, Regex)>> {
+    static HOST_WITHOUT_SCHEME: OnceLock<Regex> = OnceLock::new();
+    let host_without_scheme = HOST_WITHOUT_SCHEME.get_or_init(|| Regex::new(r"^([^:/?]*)\.").expect("invalid host regex"));
+    let with_scheme = host_without_scheme.replace(pattern, "https://$1.");
+    let expected = Url::parse("https://_____")
+        .and_then(|base| base.join(&with_scheme.replace('*', "_____")))
+        .ok()?;
+
+    let mut parts = vec![
+        (UrlPart::Host, simple_url_part_regex(&host_with_port(&expected), false)?),
+        (UrlPart::Path, simple_url_part_regex(expected.path(), true)?),
+    ];
+    if let Some(fragment) = expected.fragment().filter(|fragment| !fragment.is_empty()) {
+        parts.push((UrlPart::Fragment, simple_url_part_regex(fragment, false)?));
+    }
+    for (key, value) in expected.query_pairs() {
+        parts.push((UrlPart::Query(key.to_string()), simple_url_part_regex(&value, false)?));
+    }
+    Some(parts)
+}
+
+fn simple_url_part_regex(pattern: &str, is_path: bool) -> Option<Regex> {
+    let mut escaped = regex::escape(pattern).replace("_____", ".*");
+    if is_path {
+        let trimmed = escaped.strip_prefix('/').unwrap_or(&escaped);
+        escaped = format!(r"\/?{}\/?", trimmed.strip_suffix('/').unwrap_or(trimmed));
+    }
+    Regex::new(&format!("(?i)^{}$", escaped)).ok()
+}
+
+fn eval_simple_url_target(actual: &Url, parts: &[(UrlPart, Regex)]) -> bool {
+    parts.iter().all(|(part, regex)| {
+        let value = match part {
+            UrlPart::Host => host_with_port(actual),
+            UrlPart::Path => actual.path().to_string(),
+            UrlPart::Fragment => actual.fragment().unwrap_or("").to_string(),
+            UrlPart::Query(key) => actual
+                .query_pairs()
+                .find(|(actual_key, _)| actual_key == key)
+                .map(|(_, value)| value.to_string())
+                .unwrap_or_default(),
+        };
+        regex.is_match(&value)
+    })
+}
+
+fn host_with_port(url: &Url) -> String {
+    let host = url.host_str().unwrap_or("");
+    match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    }
+}
+
 type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
 
 pub fn decrypt_string(encrypted_string: &str, decryption_key: &str) -> Option<String> {
@@ -116,3 +296,121 @@ pub fn decrypt_string(encrypted_string: &str, decryption_key: &str) -> Option<St
 
     Some(decrypted_str)
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::model::{UrlTarget, UrlTargetType};
+    use crate::util::{compile_url_targets, hash_value_string, is_url_targeted};
+
+    fn simple(pattern: &str) -> UrlTarget {
+        UrlTarget {
+            pattern: pattern.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_simple_url_targets() {
+        let targets = vec![simple("https://www.example.com/pricing")];
+        assert!(is_url_targeted("https://www.example.com/pricing", &targets));
+        assert!(is_url_targeted("https://www.example.com/pricing/?utm_source=ad", &targets));
+        assert!(is_url_targeted("https://WWW.EXAMPLE.COM/Pricing", &targets));
+        assert!(!is_url_targeted("https://www.example.com/pricing/enterprise", &targets));
+        assert!(!is_url_targeted("https://shop.example.com/pricing", &targets));
+
+        let targets = vec![simple("*.example.com/blog/*")];
+        assert!(is_url_targeted("https://docs.example.com/blog/post-1", &targets));
+        assert!(!is_url_targeted("https://docs.example.com/news/post-1", &targets));
+
+        // path only patterns match on any host, query params have to be present
+        let targets = vec![simple("/checkout?step=2")];
+        assert!(is_url_targeted("https://example.com/checkout?step=2&coupon=x", &targets));
+        assert!(!is_url_targeted("https://example.com/checkout?step=1", &targets));
+        assert!(is_url_targeted("/checkout?step=2", &targets));
+    }
+
+    #[test]
+    fn test_url_targets_are_compiled_once() {
+        let targets = vec![
+            simple("/pricing"),
+            UrlTarget {
+                target_type: UrlTargetType::Regex,
+                pattern: "(".to_string(),
+                ..Default::default()
+            },
+        ];
+        compile_url_targets(&targets);
+        // clones carry the compiled regexes along, as the experiments handed out by the repository do
+        let cloned = targets.clone();
+        assert!(cloned.iter().all(|target| target.compiled.0.get().is_some()));
+        assert!(is_url_targeted("https://example.com/pricing", &cloned[..1]));
+        assert!(!is_url_targeted("https://example.com/pricing", &cloned[1..]));
+    }
+
+    #[test]
+    fn test_regex_and_excluded_url_targets() {
+        let targets = vec![
+            UrlTarget {
+                target_type: UrlTargetType::Regex,
+                pattern: "^/products/\\d+$".to_string(),
+                ..Default::default()
+            },
+            UrlTarget {
+                include: false,
+                pattern: "/products/42".to_string(),
+                ..Default::default()
+            },
+        ];
+        assert!(is_url_targeted("https://example.com/products/7", &targets));
+        assert!(!is_url_targeted("https://example.com/products/42", &targets));
+        assert!(!is_url_targeted("https://example.com/products/shoes", &targets));
+
+        // only excludes means everything else is targeted
+        let targets = vec![UrlTarget {
+            include: false,
+            pattern: "/admin/*".to_string(),
+            ..Default::default()
+        }];
+        assert!(is_url_targeted("https://example.com/home", &targets));
+        assert!(!is_url_targeted("https://example.com/admin/users", &targets));
+        assert!(!is_url_targeted("https://example.com/home", &[]));
+    }
+
+    #[test]
+    fn test_hash_value_string() {
+        // expectations are what String(value) gives in javascript, with falsy values treated as missing
+        // like the javascript and python sdks do
+        let cases = json!([
+            ["string", "abc", "abc"],
+            ["empty string", "", ""],
+            ["integer", 123, "123"],
+            ["negative integer", -5, "-5"],
+            ["zero", 0, ""],
+            ["zero float", 0.0, ""],
+            ["negative zero", -0.0, ""],
+            ["whole float", 123.0, "123"],
+            ["float", 1.5, "1.5"],
+            ["float sum", 0.30000000000000004, "0.30000000000000004"],
+            ["negative float", -123.456, "-123.456"],
+            ["small float", 0.000001, "0.000001"],
+            ["tiny float", 1e-7, "1e-7"],
+            ["tiny float with fraction", -1.5e-9, "-1.5e-9"],
+            ["large float", 1e20, "100000000000000000000"],
+            ["huge float", 1e21, "1e+21"],
+            ["huge float with fraction", 1.5e300, "1.5e+300"],
+            ["integer beyond 2^53", 9007199254740993i64, "9007199254740992"],
+            ["large integer", 18446744073709551615u64, "18446744073709552000"],
+            ["true", true, "true"],
+            ["false", false, ""],
+            ["null", null, ""],
+            ["array", [1, 2], ""],
+            ["object", {"id": 1}, ""]
+        ]);
+        for case in cases.as_array().unwrap() {
+            let case = case.as_array().unwrap();
+            assert_eq!(hash_value_string(&case[1]), case[2].as_str().unwrap(), "case {}", case[0]);
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Display;
+use std::sync::{Arc, RwLock};
+
+use data_encoding::BASE64;
+use hmac::{Hmac, Mac};
+use log::error;
+use serde_json::Value;
+use sha2::Sha256;
+
+use crate::repository::{FeatureRepository, FeatureRepositoryError, RefreshOutcome};
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WebhookError {
+    MissingHeader(String),
+    InvalidSecret,
+    InvalidTimestamp,
+    // sent longer than tolerance_seconds ago, or that far in the future
+    Expired,
+    InvalidSignature,
+    // a webhook id that was already handled within the tolerance window
+    Replayed,
+    InvalidPayload(String),
+    Repository(FeatureRepositoryError),
+}
+
+impl Display for WebhookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebhookError::MissingHeader(name) => write!(f, "missing {} header", name),
+            WebhookError::InvalidSecret => write!(f, "invalid webhook secret"),
+            WebhookError::InvalidTimestamp => write!(f, "invalid webhook timestamp"),
+            WebhookError::Expired => write!(f, "webhook timestamp outside the tolerance window"),
+            WebhookError::InvalidSignature => write!(f, "no valid webhook signature"),
+            WebhookError::Replayed => write!(f, "webhook already received"),
+            WebhookError::InvalidPayload(message) => write!(f, "invalid webhook body: {}", message),
+            WebhookError::Repository(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for WebhookError {}
+
+// receives GrowthBook sdk webhooks, signed the Standard Webhooks way: `webhook-signature` holds
+// `v1,<base64 hmac-sha256>` of `{webhook-id}.{webhook-timestamp}.{body}`.
+// a body carrying a payload is applied right away, anything else makes the repository refresh
+#[derive(Debug, Clone)]
+pub struct WebhookReceiver {
+    pub repository: FeatureRepository,
+    pub tolerance_seconds: i64,
+    secret: Vec<u8>,
+    seen: Arc<RwLock<HashMap<String, i64>>>,
+}
+
+impl WebhookReceiver {
+    // secrets in the `whsec_<base64>` form are decoded, any other string is used as is
+    pub fn new(repository: FeatureRepository, secret: &str) -> Result<Self, WebhookError> {
+        let secret = match secret.strip_prefix("whsec_") {
+            Some(encoded) => BASE64.decode(encoded.as_bytes()).map_err(|_| WebhookError::InvalidSecret)?,
+            None => secret.as_bytes().to_vec(),
+        };
+        if secret.is_empty() {
+            return Err(WebhookError::InvalidSecret);
+        }
+        Ok(WebhookReceiver {
+            repository,
+            tolerance_seconds: 300,
+            secret,
+            seen: Arc::new(RwLock::new(HashMap::new())),
+        })
+    }
+
+    // checks signature and timestamp only, without side effects. receive() does this too
+    pub fn verify(&self, headers: &[(String, String)], body: &str) -> Result<(), WebhookError> {
+        self.verified(headers, body).map(|_| ())
+    }
+
+    // the webhook id is remembered once the webhook is verified, so the same request is refused for as long
+    // as its timestamp would still be accepted. when the repository fails the id is forgotten again,
+    // so the sender's retry of that delivery gets through
+    pub async fn receive(&self, headers: &[(String, String)], body: &str) -> Result<RefreshOutcome, WebhookError> {
+        let (id, sent_at) = self.verified(headers, body)?;
+        self.accept(id, sent_at)?;
+        let result = self.handle(body).await;
+        if let Err(WebhookError::Repository(_)) = result {
+            match self.seen.write() {
+                Ok(mut seen) => {
+                    seen.remove(id);
+                }
+                Err(_) => error!("Error writing received webhook ids"),
+            }
+        }
+        result
+    }
+
+    // the webhook id and when it was sent
+    fn verified<'a>(&self, headers: &'a [(String, String)], body: &str) -> Result<(&'a str, i64), WebhookError> {
+        let id = header(headers, "webhook-id")?;
+        let timestamp = header(headers, "webhook-timestamp")?;
+        let signatures = header(headers, "webhook-signature")?;
+
+        let sent_at: i64 = timestamp.trim().parse().map_err(|_| WebhookError::InvalidTimestamp)?;
+        let now = chrono::Utc::now().timestamp();
+        if (now - sent_at).abs() > self.tolerance_seconds {
+            return Err(WebhookError::Expired);
+        }
+
+        let mut mac = HmacSha256::new_from_slice(&self.secret).map_err(|_| WebhookError::InvalidSecret)?;
+        mac.update(format!("{}.{}.", id, timestamp).as_bytes());
+        mac.update(body.as_bytes());
+        let signed = signatures
+            .split_whitespace()
+            .filter_map(|signature| signature.strip_prefix("v1,"))
+            .filter_map(|signature| BASE64.decode(signature.as_bytes()).ok())
+            .any(|signature| mac.clone().verify_slice(&signature).is_ok());
+        if !signed {
+            return Err(WebhookError::InvalidSignature);
+        }
+        Ok((id, sent_at))
+    }
+
+    fn accept(&self, id: &str, sent_at: i64) -> Result<(), WebhookError> {
+        let now = chrono::Utc::now().timestamp();
+        match self.seen.write() {
+            Ok(mut seen) => {
+                seen.retain(|_, seen_sent_at| now - *seen_sent_at <= self.tolerance_seconds);
+                if seen.contains_key(id) {
+                    return Err(WebhookError::Replayed);
+                }
+                seen.insert(id.to_string(), sent_at);
+            }
+            Err(_) => error!("Error writing received webhook ids"),
+        }
+        Ok(())
+    }
+
+    async fn handle(&self, body: &str) -> Result<RefreshOutcome, WebhookError> {
+        let payload: Value = match body.trim() {
+            "" => Value::Null,
+            body => serde_json::from_str(body).map_err(|e| WebhookError::InvalidPayload(e.to_string()))?,
+        };
+        if payload.get("features").is_some() || payload.get("encryptedFeatures").is_some() {
+            self.repository.receive_payload(&payload).await.map_err(WebhookError::Repository)
+        } else {
+            let mut repository = self.repository.clone();
+            repository.refresh().await.map_err(WebhookError::Repository)
+        }
+    }
+
+    #[cfg(feature = "webhook-axum")]
+    pub fn router(&self, path: &str) -> axum::Router {
+        axum::Router::new()
+            .route(path, axum::routing::post(axum_handler))
+            .with_state(self.clone())
+    }
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Result<&'a str, WebhookError> {
+    headers
+        .iter()
+        .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+        .ok_or_else(|| WebhookError::MissingHeader(name.to_string()))
+}
+
+// rejected webhooks get a 4xx so GrowthBook does not retry them, failed refreshes a 502 so it does
+#[cfg(feature = "webhook-axum")]
+pub async fn axum_handler(
+    axum::extract::State(receiver): axum::extract::State<WebhookReceiver>,
+    headers: axum::http::HeaderMap,
+    body: String,
+) -> axum::http::StatusCode {
+    use axum::http::StatusCode;
+
+    let headers: Vec<(String, String)> = headers
+        .iter()
+        .filter_map(|(name, value)| value.to_str().ok().map(|value| (name.to_string(), value.to_string())))
+        .collect();
+    match receiver.receive(&headers, &body).await {
+        Ok(_) => StatusCode::NO_CONTENT,
+        Err(e) => {
+            log::warn!("Rejected webhook: {}", e);
+            match e {
+                WebhookError::InvalidPayload(_) | WebhookError::MissingHeader(_) | WebhookError::InvalidTimestamp => StatusCode::BAD_REQUEST,
+                WebhookError::Expired | WebhookError::InvalidSignature | WebhookError::InvalidSecret => StatusCode::UNAUTHORIZED,
+                WebhookError::Replayed => StatusCode::CONFLICT,
+                WebhookError::Repository(_) => StatusCode::BAD_GATEWAY,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use data_encoding::BASE64;
+    use hmac::Mac;
+    use serde_json::json;
+
+    use crate::repository::{FeatureRepository, FeatureRepositoryError, RefreshOutcome};
+    use crate::webhook::{HmacSha256, WebhookError, WebhookReceiver};
+
+    const SECRET: &str = "whsec_MfKQ9r8GKYqrTwjUPD8ILPZIo2LaLaSw";
+    const PAYLOAD: &str = r#"{"features": {"greeting": {"defaultValue": "hello"}}, "dateUpdated": "2023-08-02T19:11:46.550Z"}"#;
+
+    fn signed_headers(id: &str, sent_at: i64, body: &str) -> Vec<(String, String)> {
+        let secret = BASE64.decode(SECRET.strip_prefix("whsec_").unwrap().as_bytes()).unwrap();
+        let mut mac = HmacSha256::new_from_slice(&secret).unwrap();
+        mac.update(format!("{}.{}.{}", id, sent_at, body).as_bytes());
+        let signature = BASE64.encode(&mac.finalize().into_bytes());
+        vec![
+            ("Webhook-Id".to_string(), id.to_string()),
+            ("Webhook-Timestamp".to_string(), sent_at.to_string()),
+            ("Webhook-Signature".to_string(), format!("v1,bm90IGl0 v1,{}", signature)),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_signed_payload_is_applied() {
+        let receiver = WebhookReceiver::new(FeatureRepository::default(), SECRET).unwrap();
+        let headers = signed_headers("msg_1", chrono::Utc::now().timestamp(), PAYLOAD);
+
+        assert_eq!(receiver.receive(&headers, PAYLOAD).await, Ok(RefreshOutcome::Updated));
+        assert_eq!(
+            receiver.repository.features.read().unwrap()["greeting"].default_value,
+            Some(json!("hello"))
+        );
+        assert_eq!(receiver.receive(&headers, PAYLOAD).await, Err(WebhookError::Replayed));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_webhooks_are_rejected() {
+        let receiver = WebhookReceiver::new(FeatureRepository::default(), SECRET).unwrap();
+        let now = chrono::Utc::now().timestamp();
+
+        let headers = signed_headers("msg_1", now, PAYLOAD);
+        let tampered = PAYLOAD.replace("hello", "hacked");
+        assert_eq!(receiver.receive(&headers, &tampered).await, Err(WebhookError::InvalidSignature));
+
+        let headers = signed_headers("msg_2", now - 600, PAYLOAD);
+        assert_eq!(receiver.receive(&headers, PAYLOAD).await, Err(WebhookError::Expired));
+
+        let headers = signed_headers("msg_3", now, PAYLOAD);
+        assert_eq!(
+            receiver.receive(&headers[..2], PAYLOAD).await,
+            Err(WebhookError::MissingHeader("webhook-signature".to_string()))
+        );
+        assert!(receiver.repository.features.read().unwrap().is_empty());
+        assert_eq!(
+            WebhookReceiver::new(FeatureRepository::default(), "whsec_!!").unwrap_err(),
+            WebhookError::InvalidSecret
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_webhook_can_be_retried() {
+        let receiver = WebhookReceiver::new(FeatureRepository::default(), SECRET).unwrap();
+        let body = r#"{"features": {}, "encryptedFeatures": "abc.def"}"#;
+        let headers = signed_headers("msg_1", chrono::Utc::now().timestamp(), body);
+
+        let missing_key = Err(WebhookError::Repository(FeatureRepositoryError::MissingDecryptionKey));
+        assert_eq!(receiver.receive(&headers, body).await, missing_key);
+        assert_eq!(receiver.receive(&headers, body).await, missing_key);
+
+        // once the repository can handle it, the retried delivery is applied and only then refused
+        receiver.repository.set_decryption_key(Some("BhB1wORFmZLTDjbvstvS8w==".to_string()));
+        let body = r#"{"features": {}, "encryptedFeatures": "UqANSnJ7xTTK9y2PALtnwQ==.BZAstXrI9eh9qlvp7VinD8CKk9ZE8755vnFtkClJNYstTUwF4FKwWWq84F/DFTe+2Xlzbys83S1Ih6XIFhoigKIQeImlnzR3GJ6Bvj3REbKccw9TJz4bX3ozFzSNBbZbLAynnd9aTLK0PAYASLXKtIaAs/K0WSbV7mM95CVMt9DU7w1TKme/tQcqfEn+CJhi2WHNdEzGs18j9t7zXcRgdAvXizLzP7HdOnCmfXy9bZbpqWmAdUBZ0yhmb2PGXa5FBwet7h1MV0kRFX++WocwjA=="}"#;
+        let headers = signed_headers("msg_2", chrono::Utc::now().timestamp(), body);
+        assert_eq!(receiver.receive(&headers, body).await, Ok(RefreshOutcome::Updated));
+        assert_eq!(receiver.receive(&headers, body).await, Err(WebhookError::Replayed));
+    }
+
+    #[tokio::test]
+    async fn test_verify_has_no_side_effects() {
+        let receiver = WebhookReceiver::new(FeatureRepository::default(), SECRET).unwrap();
+        let headers = signed_headers("msg_1", chrono::Utc::now().timestamp(), PAYLOAD);
+
+        assert_eq!(receiver.verify(&headers, PAYLOAD), Ok(()));
+        assert_eq!(receiver.verify(&headers, PAYLOAD), Ok(()));
+        assert!(receiver.repository.features.read().unwrap().is_empty());
+        assert_eq!(receiver.receive(&headers, PAYLOAD).await, Ok(RefreshOutcome::Updated));
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn test_notification_without_payload_triggers_refresh() {
+        let mut mock_server = mockito::Server::new_async().await;
+        let mock = mock_server
+            .mock("GET", "/api/features/key_for_webhook")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(PAYLOAD)
+            .expect(1)
+            .create();
+        let repository = FeatureRepository {
+            api_host: mock_server.url(),
+            client_key: Some("key_for_webhook".to_string()),
+            ..Default::default()
+        };
+        let receiver = WebhookReceiver::new(repository, SECRET).unwrap();
+        let body = r#"{"type": "payload.changed"}"#;
+        let headers = signed_headers("msg_1", chrono::Utc::now().timestamp(), body);
+
+        assert_eq!(receiver.receive(&headers, body).await, Ok(RefreshOutcome::Updated));
+        mock.assert_async().await;
+        assert_eq!(receiver.repository.features.read().unwrap().len(), 1);
+    }
+
+    #[cfg(all(feature = "webhook-axum", feature = "reqwest"))]
+    #[tokio::test]
+    async fn test_axum_handler() {
+        let receiver = WebhookReceiver::new(FeatureRepository::default(), SECRET).unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(receiver.router("/webhooks/growthbook").into_make_service());
+        tokio::spawn(server);
+
+        let url = format!("http://{}/webhooks/growthbook", address);
+        let send = |headers: Vec<(String, String)>| {
+            let url = url.clone();
+            async move {
+                let mut request = reqwest::Client::new().post(url).body(PAYLOAD);
+                for (name, value) in headers {
+                    request = request.header(name, value);
+                }
+                request.send().await.unwrap().status().as_u16()
+            }
+        };
+        let headers = signed_headers("msg_1", chrono::Utc::now().timestamp(), PAYLOAD);
+        assert_eq!(send(headers.clone()).await, 204);
+        assert_eq!(send(headers).await, 409);
+        assert_eq!(send(signed_headers("msg_2", 0, PAYLOAD)).await, 401);
+        assert_eq!(receiver.repository.features.read().unwrap().len(), 1);
+    }
+}